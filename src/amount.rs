@@ -0,0 +1,286 @@
+use std::{fmt, str::FromStr};
+
+use ethabi::ethereum_types::U256;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use strum::Display;
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum AmountError {
+    ZeroDenominator,
+    LossyConversion,
+    Overflow,
+    InvalidFormat(String),
+}
+
+impl std::error::Error for AmountError {}
+
+/// Largest number of token decimals supported, `10^77` is the largest power of
+/// ten fitting into `U256`.
+pub const MAX_DECIMALS: u8 = 77;
+
+/// `10^decimals`, the token amount of one whole token.
+fn scale(decimals: u8) -> Result<U256, AmountError> {
+    if decimals > MAX_DECIMALS {
+        return Err(AmountError::Overflow);
+    }
+    Ok(U256::exp10(decimals as usize))
+}
+
+/// Amount in pool units, i.e. the token amount divided by the pool denominator.
+///
+/// This is the unit used by the pool circuits, relayer fees and direct deposit fees.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct PoolAmount(pub u64);
+
+/// Amount in the smallest units of the pool token (or wei for native deposits).
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct TokenAmount(pub U256);
+
+impl PoolAmount {
+    pub fn new(amount: u64) -> Self {
+        Self(amount)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Converts to token units using the pool `denominator` (or `native_denominator`).
+    pub fn to_token(&self, denominator: U256) -> Result<TokenAmount, AmountError> {
+        if denominator.is_zero() {
+            return Err(AmountError::ZeroDenominator);
+        }
+        U256::from(self.0)
+            .checked_mul(denominator)
+            .map(TokenAmount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    /// Formats the amount as a decimal token value, e.g. `1.5` for 1.5 BOB.
+    pub fn format(&self, denominator: U256, decimals: u8) -> Result<String, AmountError> {
+        self.to_token(denominator)?.format(decimals)
+    }
+
+    pub fn checked_add(&self, other: PoolAmount) -> Option<PoolAmount> {
+        self.0.checked_add(other.0).map(PoolAmount)
+    }
+
+    pub fn checked_sub(&self, other: PoolAmount) -> Option<PoolAmount> {
+        self.0.checked_sub(other.0).map(PoolAmount)
+    }
+}
+
+impl TokenAmount {
+    pub fn new(amount: U256) -> Self {
+        Self(amount)
+    }
+
+    pub fn as_u256(&self) -> U256 {
+        self.0
+    }
+
+    /// Converts to pool units, failing if the amount is not a multiple of `denominator`
+    /// or does not fit into the pool amount range.
+    pub fn to_pool(&self, denominator: U256) -> Result<PoolAmount, AmountError> {
+        if denominator.is_zero() {
+            return Err(AmountError::ZeroDenominator);
+        }
+        let (quotient, remainder) = self.0.div_mod(denominator);
+        if !remainder.is_zero() {
+            return Err(AmountError::LossyConversion);
+        }
+        if quotient > U256::from(u64::MAX) {
+            return Err(AmountError::Overflow);
+        }
+        Ok(PoolAmount(quotient.as_u64()))
+    }
+
+    /// Parses a decimal token value such as `12.34` given the token `decimals`.
+    ///
+    /// Values with more fractional digits than the token supports are rejected,
+    /// as are `decimals` above [`MAX_DECIMALS`].
+    pub fn parse(value: &str, decimals: u8) -> Result<TokenAmount, AmountError> {
+        let invalid = || AmountError::InvalidFormat(value.to_string());
+
+        let (integer, fraction) = match value.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (value, ""),
+        };
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > decimals as usize {
+            return Err(AmountError::LossyConversion);
+        }
+
+        let scale = scale(decimals)?;
+        let integer = match integer {
+            "" => U256::zero(),
+            integer => U256::from_dec_str(integer).map_err(|_| AmountError::Overflow)?,
+        };
+        let fraction = match fraction {
+            "" => U256::zero(),
+            fraction => {
+                U256::from_dec_str(fraction).map_err(|_| invalid())?
+                    * U256::exp10(decimals as usize - fraction.len())
+            }
+        };
+
+        integer
+            .checked_mul(scale)
+            .and_then(|integer| integer.checked_add(fraction))
+            .map(TokenAmount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    /// Formats the amount as a decimal value with trailing fractional zeros removed.
+    ///
+    /// Fails for `decimals` above [`MAX_DECIMALS`].
+    pub fn format(&self, decimals: u8) -> Result<String, AmountError> {
+        let (integer, fraction) = self.0.div_mod(scale(decimals)?);
+        if fraction.is_zero() {
+            return Ok(integer.to_string());
+        }
+        let fraction = format!(
+            "{:0>width$}",
+            fraction.to_string(),
+            width = decimals as usize
+        );
+        Ok(format!("{}.{}", integer, fraction.trim_end_matches('0')))
+    }
+}
+
+impl From<u64> for PoolAmount {
+    fn from(amount: u64) -> Self {
+        PoolAmount(amount)
+    }
+}
+
+impl From<U256> for TokenAmount {
+    fn from(amount: U256) -> Self {
+        TokenAmount(amount)
+    }
+}
+
+impl FromStr for PoolAmount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u64>()
+            .map(PoolAmount)
+            .map_err(|_| AmountError::InvalidFormat(s.to_string()))
+    }
+}
+
+impl fmt::Display for PoolAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// The relayer reports amounts either as numbers or as decimal strings.
+impl<'de> Deserialize<'de> for PoolAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_number_from_string(deserializer).map(PoolAmount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::ethereum_types::U256;
+
+    use super::{AmountError, PoolAmount, TokenAmount, MAX_DECIMALS};
+
+    #[test]
+    fn token_to_pool_roundtrip() {
+        let denominator = U256::exp10(9);
+        let token = TokenAmount::parse("1.5", 18).unwrap();
+
+        let pool = token.to_pool(denominator).unwrap();
+        assert_eq!(pool, PoolAmount(1_500_000_000));
+        assert_eq!(pool.to_token(denominator).unwrap(), token);
+    }
+
+    #[test]
+    fn lossy_conversion_is_rejected() {
+        let denominator = U256::exp10(9);
+        let token = TokenAmount(U256::exp10(9) + 1);
+
+        assert_eq!(
+            token.to_pool(denominator),
+            Err(AmountError::LossyConversion)
+        );
+        assert_eq!(
+            TokenAmount::parse("0.0000001", 6),
+            Err(AmountError::LossyConversion)
+        );
+        assert_eq!(
+            token.to_pool(U256::zero()),
+            Err(AmountError::ZeroDenominator)
+        );
+    }
+
+    #[test]
+    fn pool_overflow_is_rejected() {
+        let token = TokenAmount(U256::from(u64::MAX) + 1);
+        assert_eq!(token.to_pool(U256::one()), Err(AmountError::Overflow));
+    }
+
+    #[test]
+    fn format_with_decimals() {
+        assert_eq!(TokenAmount(U256::exp10(18)).format(18).unwrap(), "1");
+        assert_eq!(
+            TokenAmount(U256::from(1_050_000u64)).format(6).unwrap(),
+            "1.05"
+        );
+        assert_eq!(TokenAmount(U256::from(5u64)).format(6).unwrap(), "0.000005");
+        assert_eq!(
+            PoolAmount(100_000_000).format(U256::exp10(9), 18).unwrap(),
+            "0.1"
+        );
+    }
+
+    #[test]
+    fn unsupported_decimals_are_rejected() {
+        assert_eq!(
+            TokenAmount::parse("1", MAX_DECIMALS + 1),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            TokenAmount(U256::one()).format(u8::MAX),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            TokenAmount(U256::one()).format(MAX_DECIMALS).unwrap(),
+            format!("0.{:0>77}", 1)
+        );
+    }
+
+    #[test]
+    fn pool_amount_from_string() {
+        let amount: PoolAmount = serde_json::from_str("\"42\"").unwrap();
+        assert_eq!(amount, PoolAmount(42));
+        let amount: PoolAmount = serde_json::from_str("42").unwrap();
+        assert_eq!(amount, PoolAmount(42));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{cell::RefCell, fmt};
+
+use super::{LogFormat, TelemetryKind};
+
+/// Variable that selects the environment, `local` if not set.
+pub const ENVIRONMENT_VAR: &str = "APP_ENVIRONMENT";
+
+/// Comma separated list of extra profiles layered on top of the environment file.
+pub const PROFILES_VAR: &str = "APP_PROFILES";
+
+thread_local! {
+    /// Environment of the settings being deserialized by [`super::ConfigLoader`].
+    static LOADING: RefCell<Option<Environment>> = const { RefCell::new(None) };
+}
+
+/// Deployment environment the service runs in.
+///
+/// Custom environments are accepted by [`super::ConfigLoader`] only if a config
+/// file with the same name exists.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Environment {
+    Local,
+    Test,
+    Staging,
+    Production,
+    Custom(String),
+}
+
+impl Environment {
+    /// Maps known names to their variants and anything else to [`Environment::Custom`].
+    pub fn new(id: String) -> Self {
+        match id.to_lowercase().as_str() {
+            "local" => Environment::Local,
+            "test" => Environment::Test,
+            "staging" => Environment::Staging,
+            "production" => Environment::Production,
+            _ => Environment::Custom(id),
+        }
+    }
+
+    /// The environment selected by [`ENVIRONMENT_VAR`].
+    pub fn current() -> Self {
+        Environment::new(std::env::var(ENVIRONMENT_VAR).unwrap_or_else(|_| "local".to_string()))
+    }
+
+    /// The environment of the settings being loaded by [`super::ConfigLoader`],
+    /// [`Environment::current`] otherwise. Used for setting defaults.
+    pub fn loading() -> Self {
+        LOADING
+            .with(|loading| loading.borrow().clone())
+            .unwrap_or_else(Environment::current)
+    }
+
+    /// Runs `f` with `environment` returned by [`Environment::loading`].
+    pub(super) fn while_loading<R>(environment: Option<Environment>, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<Environment>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                LOADING.with(|loading| *loading.borrow_mut() = previous);
+            }
+        }
+
+        let _restore = Restore(LOADING.with(|loading| loading.replace(environment)));
+        f()
+    }
+
+    /// Profiles listed in [`PROFILES_VAR`].
+    pub fn current_profiles() -> Vec<String> {
+        std::env::var(PROFILES_VAR)
+            .map(|profiles| {
+                profiles
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|profile| !profile.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Environment::Local => "local",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+            Environment::Custom(id) => id,
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Environment::Custom(_))
+    }
+
+    /// Telemetry used when `telemetry.kind` is not set.
+    pub fn default_telemetry_kind(&self) -> TelemetryKind {
+        match self {
+            Environment::Staging | Environment::Production => TelemetryKind::Jaeger,
+            _ => TelemetryKind::Stdout,
+        }
+    }
+
+    /// Log format used when `telemetry.log_format` is not set.
+    pub fn default_log_format(&self) -> LogFormat {
+        match self {
+            Environment::Local | Environment::Test => LogFormat::Pretty,
+            _ => LogFormat::Json,
+        }
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Accepts known environments only, custom ones are resolved by [`super::ConfigLoader`].
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match Environment::new(s) {
+            Environment::Custom(id) => Err(format!(
+                "unknown environment {}, expected local, test, staging or production",
+                id
+            )),
+            environment => Ok(environment),
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::path::{Path, PathBuf};
+
+use config::{Config, ConfigError, FileFormat, Value};
+
+use super::{validate::Validate, Environment};
+
+/// File formats recognized by [`ConfigLoader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub const ALL: [ConfigFormat; 3] = [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json];
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            ConfigFormat::Yaml => &["yaml", "yml"],
+            ConfigFormat::Toml => &["toml"],
+            ConfigFormat::Json => &["json"],
+        }
+    }
+
+    fn file_format(&self) -> FileFormat {
+        match self {
+            ConfigFormat::Yaml => FileFormat::Yaml,
+            ConfigFormat::Toml => FileFormat::Toml,
+            ConfigFormat::Json => FileFormat::Json,
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        ConfigFormat::ALL
+            .into_iter()
+            .find(|format| format.extensions().contains(&extension))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ConfigFile {
+    name: String,
+    required: bool,
+    /// Missing files are reported as an unknown environment.
+    environment: bool,
+}
+
+/// Builds settings from config files, environment variables and overrides.
+///
+/// Sources are merged in order: files as added, then environment variables, then
+/// overrides. File names without an extension are looked up with every enabled
+/// format, e.g. `base` matches `base.yaml` or `base.toml`.
+///
+/// [`ConfigLoader::default`] reproduces [`super::get_config`]: `configuration/base`,
+/// `configuration/{APP_ENVIRONMENT}`, profiles from `APP_PROFILES` and `APP_` prefixed
+/// variables with `__` as separator.
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    dir: PathBuf,
+    formats: Vec<ConfigFormat>,
+    files: Vec<ConfigFile>,
+    /// Environment variables are read only if a prefix is set.
+    env_prefix: Option<String>,
+    env_prefix_separator: String,
+    env_separator: String,
+    overrides: Vec<(String, Value)>,
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        let mut loader = Self::new()
+            .dir("configuration")
+            .file("base")
+            .environment(&Environment::current());
+        for profile in Environment::current_profiles() {
+            loader = loader.profile(&profile);
+        }
+        loader.env_prefix("APP")
+    }
+}
+
+impl ConfigLoader {
+    /// Creates a loader without any sources, reading files from the working directory.
+    pub fn new() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            formats: ConfigFormat::ALL.to_vec(),
+            files: Vec::new(),
+            env_prefix: None,
+            env_prefix_separator: "_".to_string(),
+            env_separator: "__".to_string(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Directory that relative file names are resolved against.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Restricts the formats used to look up files without an extension.
+    pub fn formats(mut self, formats: &[ConfigFormat]) -> Self {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Adds a file that must exist.
+    pub fn file(mut self, name: &str) -> Self {
+        self.files.push(ConfigFile {
+            name: name.to_string(),
+            required: true,
+            environment: false,
+        });
+        self
+    }
+
+    /// Adds a file that is skipped if missing.
+    pub fn optional_file(mut self, name: &str) -> Self {
+        self.files.push(ConfigFile {
+            name: name.to_string(),
+            required: false,
+            environment: false,
+        });
+        self
+    }
+
+    /// Adds the file of `environment`, which must exist for custom environments.
+    pub fn environment(mut self, environment: &Environment) -> Self {
+        self.files.push(ConfigFile {
+            name: environment.as_str().to_string(),
+            required: true,
+            environment: true,
+        });
+        self
+    }
+
+    /// Layers a profile file, e.g. `eu-west`, on top of the files added so far.
+    pub fn profile(self, name: &str) -> Self {
+        self.file(name)
+    }
+
+    /// Drops previously added files, e.g. the ones set by [`ConfigLoader::default`].
+    pub fn clear_files(mut self) -> Self {
+        self.files.clear();
+        self
+    }
+
+    /// Reads variables such as `{prefix}_WEB3__POOL_ADDRESS`.
+    pub fn env_prefix(mut self, prefix: &str) -> Self {
+        self.env_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Separator between the prefix and the key, `_` by default.
+    pub fn env_prefix_separator(mut self, separator: &str) -> Self {
+        self.env_prefix_separator = separator.to_string();
+        self
+    }
+
+    /// Separator between nested keys, `__` by default.
+    pub fn env_separator(mut self, separator: &str) -> Self {
+        self.env_separator = separator.to_string();
+        self
+    }
+
+    /// Ignores environment variables.
+    pub fn without_env(mut self) -> Self {
+        self.env_prefix = None;
+        self
+    }
+
+    /// Sets `key` regardless of files and environment, e.g. `web3.gas_limit`.
+    pub fn set_override(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.overrides.push((key.to_string(), value.into()));
+        self
+    }
+
+    pub fn build(&self) -> Result<Config, ConfigError> {
+        let mut builder = Config::builder();
+        for file in &self.files {
+            match self.resolve(&file.name)? {
+                Some((path, format)) => {
+                    builder = builder.add_source(
+                        config::File::from(path)
+                            .format(format.file_format())
+                            .required(file.required),
+                    );
+                }
+                None if file.environment && Environment::new(file.name.clone()).is_custom() => {
+                    return Err(ConfigError::Message(format!(
+                        "unknown environment {}, expected local, test, staging, production \
+                         or a configuration file with this name in {}",
+                        file.name,
+                        self.dir.display()
+                    )))
+                }
+                None if file.required => {
+                    return Err(ConfigError::Message(format!(
+                        "configuration file {} not found in {}",
+                        file.name,
+                        self.dir.display()
+                    )))
+                }
+                None => {}
+            }
+        }
+
+        if let Some(prefix) = &self.env_prefix {
+            builder = builder.add_source(
+                config::Environment::with_prefix(prefix)
+                    .prefix_separator(&self.env_prefix_separator)
+                    .separator(&self.env_separator),
+            );
+        }
+
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key.as_str(), value.clone())?;
+        }
+
+        builder.build()
+    }
+
+    /// Deserializes the merged sources. Defaults such as
+    /// [`super::TelemetrySettings::log_format`] follow the environment added with
+    /// [`ConfigLoader::environment`].
+    pub fn load<S>(&self) -> Result<S, ConfigError>
+    where
+        for<'a> S: serde::Deserialize<'a>,
+    {
+        let config = self.build()?;
+        Environment::while_loading(self.loaded_environment(), || config.try_deserialize::<S>())
+    }
+
+    /// Same as [`ConfigLoader::load`], but also runs [`Validate`].
+    pub fn load_validated<S>(&self) -> Result<S, ConfigError>
+    where
+        for<'a> S: serde::Deserialize<'a> + Validate,
+    {
+        let settings: S = self.load()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// The last environment added with [`ConfigLoader::environment`].
+    fn loaded_environment(&self) -> Option<Environment> {
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.environment)
+            .map(|file| Environment::new(file.name.clone()))
+    }
+
+    pub(super) fn directory(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Finds the file for `name`, trying every enabled format if it has no extension.
+    fn resolve(&self, name: &str) -> Result<Option<(PathBuf, ConfigFormat)>, ConfigError> {
+        let path = self.dir.join(name);
+        if path.extension().is_some() {
+            let format = ConfigFormat::from_path(&path)
+                .filter(|format| self.formats.contains(format))
+                .ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "unsupported configuration file format: {}",
+                        path.display()
+                    ))
+                })?;
+            return Ok(path.exists().then_some((path, format)));
+        }
+
+        for format in &self.formats {
+            for extension in format.extensions() {
+                let path = path.with_extension(extension);
+                if path.exists() {
+                    return Ok(Some((path, *format)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde::Deserialize;
+
+    use crate::configuration::{Environment, LogFormat, TelemetrySettings};
+
+    use super::{ConfigFormat, ConfigLoader};
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        name: String,
+        port: u16,
+        nested: Nested,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Nested {
+        value: String,
+    }
+
+    fn config_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("zkbob-config-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sources_are_merged_in_order() {
+        let dir = config_dir("merge");
+        fs::write(
+            dir.join("base.yaml"),
+            "name: base\nport: 80\nnested:\n  value: yaml\n",
+        )
+        .unwrap();
+        fs::write(dir.join("local.toml"), "port = 8080\n").unwrap();
+        let prefix = format!("ZKBOB_LOADER_TEST_{}", std::process::id());
+        let var = format!("{}-NESTED.VALUE", prefix);
+        std::env::set_var(&var, "env");
+
+        // separators apply regardless of the order of the calls
+        let result = ConfigLoader::new()
+            .dir(&dir)
+            .env_prefix_separator("-")
+            .env_separator(".")
+            .file("base")
+            .file("local")
+            .optional_file("missing")
+            .env_prefix(&prefix)
+            .set_override("name", "override")
+            .load::<Settings>();
+        std::env::remove_var(&var);
+        fs::remove_dir_all(dir).unwrap();
+
+        let settings = result.unwrap();
+        assert_eq!(settings.name, "override");
+        assert_eq!(settings.port, 8080);
+        assert_eq!(settings.nested.value, "env");
+    }
+
+    #[test]
+    fn custom_environment_requires_file() {
+        let dir = config_dir("environment");
+        fs::write(dir.join("base.yaml"), "name: base\n").unwrap();
+        fs::write(dir.join("eu-west.yaml"), "name: eu-west\n").unwrap();
+
+        let loader = ConfigLoader::new().dir(&dir).file("base");
+        let unknown = loader
+            .clone()
+            .environment(&Environment::new("prod".to_string()))
+            .build()
+            .unwrap_err();
+        assert!(unknown.to_string().contains("unknown environment prod"));
+        assert!(loader
+            .environment(&Environment::new("eu-west".to_string()))
+            .build()
+            .is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_required_file_and_disabled_format_are_errors() {
+        let dir = config_dir("missing");
+        fs::write(dir.join("base.json"), r#"{"name": "base"}"#).unwrap();
+
+        let loader = ConfigLoader::new().dir(&dir).formats(&[ConfigFormat::Yaml]);
+        assert!(loader.clone().file("base").build().is_err());
+        assert!(loader.clone().file("base.json").build().is_err());
+        assert!(loader.optional_file("base").build().is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn defaults_follow_loaded_environment() {
+        let dir = config_dir("defaults");
+        fs::write(
+            dir.join("base.yaml"),
+            "log_level: INFO\nservice_name: test\n",
+        )
+        .unwrap();
+        fs::write(dir.join("production.yaml"), "service_name: production\n").unwrap();
+        fs::write(dir.join("local.yaml"), "service_name: local\n").unwrap();
+
+        let load = |environment: Environment| {
+            ConfigLoader::new()
+                .dir(&dir)
+                .file("base")
+                .environment(&environment)
+                .without_env()
+                .load::<TelemetrySettings>()
+                .unwrap()
+        };
+        let production = load(Environment::Production);
+        let local = load(Environment::Local);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(production.log_format(), LogFormat::Json);
+        assert_eq!(local.log_format(), LogFormat::Pretty);
+    }
+}
@@ -0,0 +1,578 @@
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use std::{collections::HashMap, path::PathBuf, string::ToString, time::Duration};
+
+pub mod environment;
+pub mod loader;
+pub mod secret;
+pub mod validate;
+pub mod watch;
+
+pub use environment::Environment;
+pub use loader::{ConfigFormat, ConfigLoader};
+pub use secret::Secret;
+pub use validate::{Validate, ValidationError, ValidationErrors};
+pub use watch::ConfigWatcher;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApplicationSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub telemetry: TelemetrySettings,
+}
+
+#[derive(Debug, Serialize, Deserialize, strum::EnumString, Clone)]
+pub enum TelemetryKind {
+    #[strum(serialize = "stdout")]
+    Stdout,
+    #[strum(serialize = "jaeger")]
+    Jaeger,
+    #[strum(serialize = "otlp")]
+    Otlp,
+}
+
+#[derive(Debug, Serialize, Deserialize, strum::EnumString, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// OTLP over gRPC, default collector endpoint is `http://localhost:4317`.
+    #[strum(serialize = "grpc")]
+    Grpc,
+    /// OTLP protobuf over HTTP, default collector endpoint is `http://localhost:4318/v1/traces`.
+    #[strum(serialize = "http")]
+    Http,
+}
+
+#[derive(Debug, Serialize, Deserialize, strum::EnumString, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpCompression {
+    #[strum(serialize = "gzip")]
+    Gzip,
+}
+
+/// Exporter options used when `kind` is `Otlp`, the collector address is `endpoint`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct OtlpSettings {
+    pub protocol: OtlpProtocol,
+    /// Sent with every export, as gRPC metadata for the `Grpc` protocol.
+    pub headers: HashMap<String, Secret<String>>,
+    /// Only supported with the `Http` protocol.
+    pub compression: Option<OtlpCompression>,
+    pub timeout_sec: u64,
+}
+
+impl Default for OtlpSettings {
+    fn default() -> Self {
+        Self {
+            protocol: OtlpProtocol::Grpc,
+            headers: HashMap::new(),
+            compression: None,
+            timeout_sec: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, strum::EnumString, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Bunyan JSON.
+    #[strum(serialize = "json")]
+    Json,
+    /// Human readable lines.
+    #[strum(serialize = "pretty")]
+    Pretty,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TelemetrySettings {
+    /// Defaults to [`Environment::default_telemetry_kind`] of `environment`,
+    /// only used if `outputs` is empty.
+    #[serde(default)]
+    pub kind: Option<TelemetryKind>,
+    pub endpoint: Option<String>,
+    /// Default level, see `log_directives`.
+    pub log_level: LogLevel,
+    /// `target=level` directives such as `hyper=warn` overriding `log_level`
+    /// for some modules, in `RUST_LOG` syntax.
+    #[serde(default)]
+    pub log_directives: Vec<String>,
+    pub service_name: String,
+    /// Format of stdout logs, defaults to [`Environment::default_log_format`]
+    /// of `environment`, only used if `outputs` is empty.
+    #[serde(default)]
+    pub log_format: Option<LogFormat>,
+    #[serde(default)]
+    pub otlp: OtlpSettings,
+    /// Where logs and spans go. If empty, a single output is derived from
+    /// `kind`, `log_format`, `endpoint` and `otlp`.
+    #[serde(default)]
+    pub outputs: Vec<TelemetryOutput>,
+    /// Which traces are exported, all by default.
+    #[serde(default)]
+    pub sampling: SamplingSettings,
+    /// Added to the resource of exported traces, next to the service version
+    /// and `deployment.environment`.
+    #[serde(default)]
+    pub resource_attributes: HashMap<String, String>,
+    /// Values hidden from logs and exported spans.
+    #[serde(default)]
+    pub redaction: RedactionSettings,
+    /// Trace context headers sent to and accepted from other services.
+    /// Defaults to W3C `traceparent`, plus Jaeger `uber-trace-id` if spans go to Jaeger.
+    #[serde(default)]
+    pub propagation: Option<Vec<Propagator>>,
+    /// Environment the settings were loaded for, see [`Environment::loading`].
+    /// Reported as `deployment.environment` of exported traces.
+    #[serde(skip, default = "Environment::loading")]
+    pub environment: Environment,
+}
+
+impl TelemetrySettings {
+    pub fn kind(&self) -> TelemetryKind {
+        self.kind
+            .clone()
+            .unwrap_or_else(|| self.environment.default_telemetry_kind())
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+            .unwrap_or_else(|| self.environment.default_log_format())
+    }
+
+    /// `outputs`, or the outputs matching `kind` if none are listed.
+    ///
+    /// `Jaeger` and `Otlp` add a `Pretty` output which, unlike the one of
+    /// [`crate::telemetry::telemetry::init_jaeger`], prints event targets and
+    /// follows `RUST_LOG` like any other output.
+    pub fn resolved_outputs(&self) -> Vec<TelemetryOutput> {
+        if !self.outputs.is_empty() {
+            return self.outputs.clone();
+        }
+        let stdout = match self.log_format() {
+            LogFormat::Json => OutputKind::Bunyan,
+            LogFormat::Pretty => OutputKind::Pretty,
+        };
+        let kinds = match self.kind() {
+            TelemetryKind::Stdout => vec![stdout],
+            TelemetryKind::Jaeger => vec![
+                OutputKind::Pretty,
+                OutputKind::Jaeger {
+                    endpoint: self.endpoint.clone(),
+                },
+            ],
+            TelemetryKind::Otlp => vec![
+                OutputKind::Pretty,
+                OutputKind::Otlp {
+                    endpoint: self.endpoint.clone(),
+                    settings: self.otlp.clone(),
+                },
+            ],
+        };
+        kinds
+            .into_iter()
+            .map(|kind| TelemetryOutput {
+                kind,
+                log_level: None,
+            })
+            .collect()
+    }
+
+    /// `propagation`, or the default for the resolved outputs.
+    pub fn propagators(&self) -> Vec<Propagator> {
+        if let Some(propagation) = &self.propagation {
+            return propagation.clone();
+        }
+        let jaeger = self
+            .resolved_outputs()
+            .iter()
+            .any(|output| matches!(output.kind, OutputKind::Jaeger { .. }));
+        if jaeger {
+            vec![Propagator::TraceContext, Propagator::Jaeger]
+        } else {
+            vec![Propagator::TraceContext]
+        }
+    }
+}
+
+/// Format of trace context headers.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Propagator {
+    /// W3C `traceparent` and `tracestate`.
+    TraceContext,
+    /// Jaeger `uber-trace-id`.
+    Jaeger,
+}
+
+/// A destination of logs or spans with its own filter.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TelemetryOutput {
+    #[serde(flatten)]
+    pub kind: OutputKind,
+    /// Defaults to [`TelemetrySettings::log_level`].
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum OutputKind {
+    /// Bunyan JSON on stdout.
+    Bunyan,
+    /// Human readable lines on stdout.
+    Pretty,
+    /// Bunyan JSON appended to `path`, written from a background thread.
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: FileRotation,
+        /// Starts a new file once the current one would exceed this size.
+        #[serde(default)]
+        max_size_mb: Option<u64>,
+        /// Number of rotated files to keep, all are kept if unset.
+        #[serde(default)]
+        max_files: Option<usize>,
+    },
+    /// Spans sent to a Jaeger agent at `endpoint` (`host:port`).
+    Jaeger { endpoint: Option<String> },
+    /// Spans sent to an OTLP collector at `endpoint`.
+    Otlp {
+        endpoint: Option<String>,
+        #[serde(flatten)]
+        settings: OtlpSettings,
+    },
+    /// Bunyan JSON that is formatted and dropped.
+    Sink,
+}
+
+/// Field names redacted by default.
+pub const DEFAULT_REDACTED_FIELDS: &[&str] = &[
+    "secret_key",
+    "private_key",
+    "memo",
+    "deposit_signature",
+    "proof",
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RedactionSettings {
+    /// Fields whose values are replaced entirely, compared case-insensitively.
+    pub fields: Vec<String>,
+    /// Replace runs of 64 or more hex digits in all values, e.g. private keys
+    /// and deposit signatures. Off by default, as it also hides 32 byte
+    /// hashes and calldata.
+    pub hex_keys: bool,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        Self {
+            fields: DEFAULT_REDACTED_FIELDS
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+            hex_keys: false,
+        }
+    }
+}
+
+/// Deserialized from flat fields, e.g. `{sampler: ratio, ratio: 0.1}`, with
+/// `sampler` defaulting to `always`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(try_from = "SamplingFields")]
+pub struct SamplingSettings {
+    #[serde(flatten)]
+    pub sampler: SamplerKind,
+    /// Follow the decision of the parent span, e.g. of the calling service,
+    /// and apply `sampler` to root spans only.
+    pub parent_based: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "sampler", rename_all = "snake_case")]
+pub enum SamplerKind {
+    #[default]
+    Always,
+    Never,
+    /// Exports the given fraction of traces, chosen by trace id.
+    Ratio {
+        ratio: f64,
+    },
+    /// Exports at most the given number of traces per second.
+    RateLimited {
+        traces_per_second: f64,
+    },
+}
+
+/// Fields of [`SamplingSettings`] as written in the configuration.
+///
+/// `SamplerKind` is not flattened into `SamplingSettings` when deserializing,
+/// as serde ignores the defaults of flattened fields.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SamplingFields {
+    sampler: Option<String>,
+    ratio: Option<f64>,
+    traces_per_second: Option<f64>,
+    parent_based: bool,
+}
+
+impl TryFrom<SamplingFields> for SamplingSettings {
+    type Error = String;
+
+    fn try_from(fields: SamplingFields) -> Result<Self, Self::Error> {
+        let missing = |field: &str| format!("missing field `{}`", field);
+        let sampler = match fields.sampler.as_deref().unwrap_or("always") {
+            "always" => SamplerKind::Always,
+            "never" => SamplerKind::Never,
+            "ratio" => SamplerKind::Ratio {
+                ratio: fields.ratio.ok_or_else(|| missing("ratio"))?,
+            },
+            "rate_limited" => SamplerKind::RateLimited {
+                traces_per_second: fields
+                    .traces_per_second
+                    .ok_or_else(|| missing("traces_per_second"))?,
+            },
+            sampler => {
+                return Err(format!(
+                    "unknown sampler `{}`, expected `always`, `never`, `ratio` or `rate_limited`",
+                    sampler
+                ))
+            }
+        };
+        Ok(Self {
+            sampler,
+            parent_based: fields.parent_based,
+        })
+    }
+}
+
+/// When a [`OutputKind::File`] output starts a new file, in addition to `max_size_mb`.
+#[derive(Debug, Serialize, Deserialize, strum::EnumString, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileRotation {
+    #[default]
+    #[strum(serialize = "never")]
+    Never,
+    /// At midnight UTC.
+    #[strum(serialize = "daily")]
+    Daily,
+}
+
+#[derive(
+    Debug,
+    Deserialize,
+    Clone,
+    Serialize,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::EnumString,
+    strum_macros::Display,
+)]
+pub enum LogLevel {
+    TRACE,
+    DEBUG,
+    INFO,
+    WARN,
+    ERROR,
+}
+
+/// Loads settings with the default [`ConfigLoader`] sources.
+pub fn get_config<S>() -> Result<S, config::ConfigError>
+where
+    for<'a> S: serde::Deserialize<'a>,
+{
+    ConfigLoader::default().load()
+}
+
+/// Same as [`get_config`], but also runs [`Validate`] and reports every invalid value at once.
+pub fn get_validated_config<S>() -> Result<S, config::ConfigError>
+where
+    for<'a> S: serde::Deserialize<'a> + Validate,
+{
+    ConfigLoader::default().load_validated()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Web3Settings {
+    pub provider_endpoint: String,
+    pub provider_timeout_sec: u64,
+    pub pool_address: String,
+    pub gas_limit: Option<u64>,
+    /// Multicall3 deployment used for batched reads, the canonical address if not set.
+    #[serde(default)]
+    pub multicall_address: Option<String>,
+    /// Inline or a `file:`/`env:` reference, see [`Secret`].
+    #[serde(skip_serializing)]
+    pub secret_key: Option<Secret<String>>,
+    /// Read-through cache for immutable chain data, disabled if not set.
+    #[serde(default)]
+    pub cache: Option<CacheSettings>,
+}
+
+/// How long a cached value stays valid.
+///
+/// Deserialized from `disabled`, `forever` or a duration such as `500ms` or `2s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum CachePolicy {
+    Disabled,
+    Forever,
+    Ttl(Duration),
+}
+
+impl TryFrom<String> for CachePolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid cache policy: {}", s))
+        };
+        match s.as_str() {
+            "disabled" => Ok(CachePolicy::Disabled),
+            "forever" => Ok(CachePolicy::Forever),
+            ttl => match ttl.strip_suffix("ms") {
+                Some(ms) => Ok(CachePolicy::Ttl(Duration::from_millis(parse(ms)?))),
+                None => match ttl.strip_suffix('s') {
+                    Some(secs) => Ok(CachePolicy::Ttl(Duration::from_secs(parse(secs)?))),
+                    None => Err(format!("invalid cache policy: {}", s)),
+                },
+            },
+        }
+    }
+}
+
+impl From<CachePolicy> for String {
+    fn from(policy: CachePolicy) -> Self {
+        match policy {
+            CachePolicy::Disabled => "disabled".to_string(),
+            CachePolicy::Forever => "forever".to_string(),
+            CachePolicy::Ttl(ttl) => format!("{}ms", ttl.as_millis()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub max_entries: usize,
+    pub pool_id: CachePolicy,
+    pub chain_id: CachePolicy,
+    pub dd_queue_address: CachePolicy,
+    /// Roots are only cached once written, so a final root never changes.
+    pub roots: CachePolicy,
+    pub block_timestamp: CachePolicy,
+    pub pool_index: CachePolicy,
+    /// Blocks behind the head after which roots and timestamps can't be reorged.
+    pub finality_depth: u64,
+    /// Upper bound for the policies of roots and timestamps that aren't final yet.
+    pub unfinalized: CachePolicy,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            pool_id: CachePolicy::Forever,
+            chain_id: CachePolicy::Forever,
+            dd_queue_address: CachePolicy::Forever,
+            roots: CachePolicy::Forever,
+            block_timestamp: CachePolicy::Forever,
+            pool_index: CachePolicy::Ttl(Duration::from_secs(2)),
+            finality_depth: 64,
+            unfinalized: CachePolicy::Ttl(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// Build information reported by a service, usually created with [`crate::build_version!`].
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Version {
+    #[serde(default)]
+    pub package_version: Option<String>,
+    pub ref_name: Option<String>,
+    pub commit_hash: Option<String>,
+    #[serde(default)]
+    pub build_timestamp: Option<String>,
+    #[serde(default)]
+    pub rustc_version: Option<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OtlpProtocol, OutputKind, Propagator, SamplerKind, TelemetrySettings};
+
+    fn telemetry(yaml: &str) -> TelemetrySettings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!("service_name: test\nlog_level: INFO\n{}", yaml),
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn kinds(telemetry: &TelemetrySettings) -> Vec<OutputKind> {
+        let outputs = telemetry.resolved_outputs();
+        assert!(outputs.iter().all(|output| output.log_level.is_none()));
+        outputs.into_iter().map(|output| output.kind).collect()
+    }
+
+    #[test]
+    fn legacy_settings_map_to_outputs() {
+        let json = telemetry("kind: Stdout\nlog_format: Json");
+        assert!(matches!(kinds(&json)[..], [OutputKind::Bunyan]));
+        assert_eq!(json.propagators(), [Propagator::TraceContext]);
+
+        let pretty = telemetry("kind: Stdout\nlog_format: Pretty");
+        assert!(matches!(kinds(&pretty)[..], [OutputKind::Pretty]));
+
+        let jaeger = telemetry("kind: Jaeger\nendpoint: localhost:6831");
+        assert!(matches!(
+            &kinds(&jaeger)[..],
+            [OutputKind::Pretty, OutputKind::Jaeger { endpoint: Some(endpoint) }]
+                if endpoint == "localhost:6831"
+        ));
+        assert_eq!(
+            jaeger.propagators(),
+            [Propagator::TraceContext, Propagator::Jaeger]
+        );
+
+        let otlp = telemetry("kind: Otlp\notlp:\n  protocol: Http");
+        assert!(matches!(
+            &kinds(&otlp)[..],
+            [OutputKind::Pretty, OutputKind::Otlp { endpoint: None, settings }]
+                if settings.protocol == OtlpProtocol::Http
+        ));
+
+        let outputs = telemetry("kind: Jaeger\noutputs:\n  - kind: sink");
+        assert!(matches!(kinds(&outputs)[..], [OutputKind::Sink]));
+        assert_eq!(outputs.propagators(), [Propagator::TraceContext]);
+    }
+
+    #[test]
+    fn sampler_defaults_to_always() {
+        let parent_based = telemetry("sampling:\n  parent_based: true").sampling;
+        assert_eq!(parent_based.sampler, SamplerKind::Always);
+        assert!(parent_based.parent_based);
+
+        let empty = telemetry("sampling: {}").sampling;
+        assert_eq!(empty.sampler, SamplerKind::Always);
+        assert!(!empty.parent_based);
+
+        let rate_limited =
+            telemetry("sampling:\n  sampler: rate_limited\n  traces_per_second: 10").sampling;
+        assert_eq!(
+            rate_limited.sampler,
+            SamplerKind::RateLimited {
+                traces_per_second: 10.0
+            }
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt, fs, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::telemetry::redact::REDACTED;
+
+/// Configuration value that never shows up in logs.
+///
+/// `Debug` and `Serialize` print `[REDACTED]`. When deserialized, the value may be
+/// given inline or as a reference resolved at load time:
+/// - `file:/run/secrets/key` reads the file and trims trailing whitespace,
+/// - `env:OTHER_VAR` reads another environment variable.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Secret<T>
+where
+    T: FromStr,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let value = resolve(&value).map_err(de::Error::custom)?;
+        // the parse error is dropped since it may contain the value itself
+        value
+            .parse()
+            .map(Secret)
+            .map_err(|_| de::Error::custom("failed to parse secret value"))
+    }
+}
+
+/// Resolves `file:` and `env:` references, returning other values unchanged.
+pub fn resolve(value: &str) -> Result<String, String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        return fs::read_to_string(path)
+            .map(|content| content.trim_end().to_string())
+            .map_err(|err| format!("failed to read secret from {}: {}", path, err));
+    }
+    if let Some(name) = value.strip_prefix("env:") {
+        return std::env::var(name)
+            .map_err(|err| format!("failed to read secret from ${}: {}", name, err));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Secret;
+
+    #[test]
+    fn references_are_resolved() {
+        let path = std::env::temp_dir().join(format!("zkbob-secret-{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+        let var = format!("ZKBOB_SECRET_TEST_{}", std::process::id());
+        std::env::set_var(&var, "from-env");
+
+        let parse = |value: &str| serde_json::from_value::<Secret<String>>(value.into());
+        let from_file = parse(&format!("file:{}", path.display()));
+        let from_env = parse(&format!("env:{}", var));
+        std::env::remove_var(&var);
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(from_file.unwrap().expose(), "from-file");
+        assert_eq!(from_env.unwrap().expose(), "from-env");
+        assert_eq!(parse("inline").unwrap().expose(), "inline");
+        assert!(parse(&format!("env:{}", var)).is_err());
+    }
+
+    #[test]
+    fn value_is_redacted() {
+        let secret = Secret::new("4c0883a69102937d".to_string());
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""[REDACTED]""#);
+    }
+}
@@ -0,0 +1,448 @@
+use std::{fmt, str::FromStr};
+
+use reqwest::Url;
+use secp256k1::SecretKey;
+use tracing_subscriber::filter::Directive;
+use web3::types::H160;
+
+use super::{
+    ApplicationSettings, OtlpProtocol, OtlpSettings, OutputKind, SamplerKind, SamplingSettings,
+    TelemetryKind, TelemetryOutput, TelemetrySettings, Web3Settings,
+};
+
+/// A single invalid value, identified by its config key path such as `web3.pool_address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+/// All problems found in a configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl ValidationErrors {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ValidationError> {
+        self.0.iter()
+    }
+
+    /// Records a problem with `key` under `path`.
+    pub fn add(&mut self, path: &str, key: &str, message: impl Into<String>) {
+        self.0.push(ValidationError {
+            path: join(path, key),
+            message: message.into(),
+        });
+    }
+
+    fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  {}: {}", error.path, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl From<ValidationErrors> for config::ConfigError {
+    fn from(errors: ValidationErrors) -> Self {
+        config::ConfigError::Message(errors.to_string())
+    }
+}
+
+/// Checks settings that deserialize fine but would fail at runtime.
+pub trait Validate {
+    /// Appends problems to `errors`, prefixing keys with `path`.
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors);
+
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        self.validate_at("", &mut errors);
+        errors.into_result()
+    }
+}
+
+impl<V: Validate> Validate for Option<V> {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        if let Some(value) = self {
+            value.validate_at(path, errors);
+        }
+    }
+}
+
+impl Validate for ApplicationSettings {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        if self.host.trim().is_empty() {
+            errors.add(path, "host", "must not be empty");
+        }
+        if self.port == 0 {
+            errors.add(path, "port", "must be between 1 and 65535");
+        }
+        self.telemetry.validate_at(&join(path, "telemetry"), errors);
+    }
+}
+
+impl Validate for TelemetrySettings {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        if self.service_name.trim().is_empty() {
+            errors.add(path, "service_name", "must not be empty");
+        }
+        for (i, directive) in self.log_directives.iter().enumerate() {
+            if let Err(err) = Directive::from_str(directive) {
+                errors.add(
+                    path,
+                    &format!("log_directives[{}]", i),
+                    format!("invalid directive {}: {}", directive, err),
+                );
+            }
+        }
+        self.sampling.validate_at(&join(path, "sampling"), errors);
+        if !self.outputs.is_empty() {
+            if self.kind.is_some() {
+                errors.add(path, "kind", "is not used when outputs are listed");
+            }
+            if self.log_format.is_some() {
+                errors.add(path, "log_format", "is not used when outputs are listed");
+            }
+            if self.endpoint.is_some() {
+                errors.add(path, "endpoint", "is not used when outputs are listed");
+            }
+            let outputs_path = join(path, "outputs");
+            for (i, output) in self.outputs.iter().enumerate() {
+                output.validate_at(&format!("{}[{}]", outputs_path, i), errors);
+            }
+            let exporters = self
+                .outputs
+                .iter()
+                .filter(|output| {
+                    matches!(
+                        output.kind,
+                        OutputKind::Jaeger { .. } | OutputKind::Otlp { .. }
+                    )
+                })
+                .count();
+            if exporters > 1 {
+                errors.add(
+                    path,
+                    "outputs",
+                    "only one jaeger or otlp output is supported",
+                );
+            }
+            return;
+        }
+        let kind = self.kind();
+        match (&kind, &self.endpoint) {
+            (TelemetryKind::Jaeger, Some(endpoint)) => {
+                check_agent_address(path, "endpoint", endpoint, errors);
+            }
+            (TelemetryKind::Stdout, Some(_)) => {
+                errors.add(path, "endpoint", "is not used by stdout telemetry");
+            }
+            (TelemetryKind::Otlp, Some(endpoint)) => {
+                check_http_url(path, "endpoint", endpoint, errors);
+            }
+            _ => {}
+        }
+        if let TelemetryKind::Otlp = kind {
+            self.otlp.validate_at(&join(path, "otlp"), errors);
+        }
+    }
+}
+
+impl Validate for TelemetryOutput {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        match &self.kind {
+            OutputKind::File {
+                path: file,
+                max_size_mb,
+                max_files,
+                ..
+            } => {
+                if file.as_os_str().is_empty() || file.file_name().is_none() {
+                    errors.add(path, "path", "expected a file path");
+                }
+                if *max_size_mb == Some(0) {
+                    errors.add(path, "max_size_mb", "must be positive");
+                }
+                if *max_files == Some(0) {
+                    errors.add(path, "max_files", "must be positive");
+                }
+            }
+            OutputKind::Jaeger {
+                endpoint: Some(endpoint),
+            } => check_agent_address(path, "endpoint", endpoint, errors),
+            OutputKind::Otlp { endpoint, settings } => {
+                if let Some(endpoint) = endpoint {
+                    check_http_url(path, "endpoint", endpoint, errors);
+                }
+                settings.validate_at(path, errors);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Validate for SamplingSettings {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        match self.sampler {
+            SamplerKind::Ratio { ratio } if !(0.0..=1.0).contains(&ratio) => {
+                errors.add(path, "ratio", "must be between 0 and 1");
+            }
+            SamplerKind::RateLimited { traces_per_second }
+                if !traces_per_second.is_finite() || traces_per_second <= 0.0 =>
+            {
+                errors.add(path, "traces_per_second", "must be positive");
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Validate for OtlpSettings {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        if self.protocol == OtlpProtocol::Grpc && self.compression.is_some() {
+            errors.add(
+                path,
+                "compression",
+                "is only supported with the http protocol",
+            );
+        }
+        if self.timeout_sec == 0 {
+            errors.add(path, "timeout_sec", "must be positive");
+        }
+        for name in self.headers.keys() {
+            let valid = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if !valid {
+                errors.add(
+                    &join(path, "headers"),
+                    name,
+                    "header name may only contain ascii letters, digits, '-' and '_'",
+                );
+            }
+        }
+    }
+}
+
+impl Validate for Web3Settings {
+    fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+        check_http_url(path, "provider_endpoint", &self.provider_endpoint, errors);
+        if self.provider_timeout_sec == 0 {
+            errors.add(path, "provider_timeout_sec", "must be positive");
+        }
+        check_address(path, "pool_address", &self.pool_address, errors);
+        if let Some(address) = &self.multicall_address {
+            check_address(path, "multicall_address", address, errors);
+        }
+        if self.gas_limit == Some(0) {
+            errors.add(path, "gas_limit", "must be positive");
+        }
+        if let Some(secret_key) = &self.secret_key {
+            if SecretKey::from_str(secret_key.expose()).is_err() {
+                errors.add(
+                    path,
+                    "secret_key",
+                    "expected a 32 byte hex encoded private key",
+                );
+            }
+        }
+        if let Some(cache) = &self.cache {
+            if cache.max_entries == 0 {
+                errors.add(&join(path, "cache"), "max_entries", "must be positive");
+            }
+        }
+    }
+}
+
+fn check_http_url(path: &str, key: &str, value: &str, errors: &mut ValidationErrors) {
+    match Url::parse(value) {
+        Ok(url) if !["http", "https"].contains(&url.scheme()) => errors.add(
+            path,
+            key,
+            format!(
+                "unsupported scheme {}, expected http or https",
+                url.scheme()
+            ),
+        ),
+        Ok(_) => {}
+        Err(err) => errors.add(path, key, format!("invalid url: {}", err)),
+    }
+}
+
+fn check_address(path: &str, key: &str, value: &str, errors: &mut ValidationErrors) {
+    if H160::from_str(value).is_err() {
+        errors.add(path, key, format!("invalid address {}", value));
+    }
+}
+
+fn check_agent_address(path: &str, key: &str, value: &str, errors: &mut ValidationErrors) {
+    let valid = matches!(
+        value.rsplit_once(':'),
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok()
+    );
+    if !valid {
+        errors.add(path, key, "expected jaeger agent address as host:port");
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        Environment, LogFormat, LogLevel, Secret, TelemetryKind, TelemetrySettings, Web3Settings,
+    };
+
+    use super::{Validate, ValidationError};
+
+    fn web3_settings() -> Web3Settings {
+        Web3Settings {
+            provider_endpoint: "http://localhost:8545".to_string(),
+            provider_timeout_sec: 10,
+            pool_address: "0x1CBE7Fd4E1D1Ed7Be0b5C2eF5A7a2EB8B3b4c9D1".to_string(),
+            gas_limit: Some(2_000_000),
+            multicall_address: None,
+            secret_key: None,
+            cache: None,
+        }
+    }
+
+    #[test]
+    fn valid_settings_pass() {
+        assert!(web3_settings().validate().is_ok());
+    }
+
+    #[test]
+    fn all_errors_are_reported_with_key_path() {
+        let settings = Web3Settings {
+            provider_endpoint: "localhost:8545".to_string(),
+            pool_address: "0x1234".to_string(),
+            secret_key: Some(Secret::new("not a key".to_string())),
+            ..web3_settings()
+        };
+        let errors = settings.validate().unwrap_err();
+        let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec!["provider_endpoint", "pool_address", "secret_key"]
+        );
+        assert!(!errors.to_string().contains("not a key"));
+
+        let telemetry = TelemetrySettings {
+            kind: Some(TelemetryKind::Stdout),
+            endpoint: Some("localhost:6831".to_string()),
+            log_level: LogLevel::INFO,
+            log_directives: vec!["hyper=warn".to_string()],
+            service_name: String::new(),
+            log_format: Some(LogFormat::Json),
+            otlp: Default::default(),
+            outputs: Vec::new(),
+            sampling: Default::default(),
+            resource_attributes: Default::default(),
+            redaction: Default::default(),
+            propagation: None,
+            environment: Environment::Production,
+        };
+        let mut errors = Default::default();
+        telemetry.validate_at("application.telemetry", &mut errors);
+        assert_eq!(
+            errors.0[0],
+            ValidationError {
+                path: "application.telemetry.service_name".to_string(),
+                message: "must not be empty".to_string(),
+            }
+        );
+        assert_eq!(errors.0.len(), 2);
+    }
+
+    #[test]
+    fn directives_and_sampling_are_validated() {
+        let yaml = r#"
+            service_name: test
+            log_level: INFO
+            log_directives:
+              - hyper=warn
+              - zkbob_utils_rs::contracts=debug
+              - reqwest=loud
+            sampling:
+              sampler: ratio
+              ratio: 1.5
+              parent_based: true
+        "#;
+        let telemetry: TelemetrySettings = config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let errors = telemetry.validate().unwrap_err();
+        let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["log_directives[2]", "sampling.ratio"]);
+        assert!(telemetry.sampling.parent_based);
+    }
+
+    #[test]
+    fn outputs_are_validated() {
+        let yaml = r#"
+            service_name: test
+            log_level: INFO
+            kind: Jaeger
+            log_format: Json
+            outputs:
+              - kind: bunyan
+              - kind: pretty
+                log_level: DEBUG
+              - kind: otlp
+                endpoint: localhost:4317
+                protocol: Grpc
+                compression: Gzip
+              - kind: jaeger
+                endpoint: localhost
+        "#;
+        let telemetry: TelemetrySettings = config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(telemetry.outputs[1].log_level, Some(LogLevel::DEBUG));
+
+        let errors = telemetry.validate().unwrap_err();
+        let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "kind",
+                "log_format",
+                "outputs[2].endpoint",
+                "outputs[2].compression",
+                "outputs[3].endpoint",
+                "outputs"
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{path::Path, time::Duration};
+
+use config::ConfigError;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{loader::ConfigLoader, validate::Validate};
+
+/// Time to wait for related file events (e.g. editors writing a temp file and
+/// renaming it) before reloading.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Keeps configuration files watched; dropping it stops reloading.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl ConfigLoader {
+    /// Loads settings and reloads them whenever a file in the config directory changes.
+    ///
+    /// New settings are validated before being published; invalid or unparsable
+    /// files are logged and the previous settings are kept. Environment variables
+    /// and overrides are applied on every reload. Must be called within a Tokio runtime.
+    pub fn watch<S>(self) -> Result<(watch::Receiver<S>, ConfigWatcher), ConfigError>
+    where
+        S: DeserializeOwned + Validate + Send + Sync + 'static,
+    {
+        let settings: S = self.load_validated()?;
+        let (sender, receiver) = watch::channel(settings);
+
+        let (events_sender, mut events) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(_) => {
+                    let _ = events_sender.send(());
+                }
+                Err(err) => tracing::warn!("config watcher error: {}", err),
+            })
+            .map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+
+        let dir = match self.directory() {
+            dir if dir.as_os_str().is_empty() => Path::new("."),
+            dir => dir,
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+
+        let task = tokio::spawn(async move {
+            while events.recv().await.is_some() {
+                tokio::time::sleep(DEBOUNCE).await;
+                while events.try_recv().is_ok() {}
+
+                match self.load_validated::<S>() {
+                    Ok(settings) => {
+                        tracing::info!("configuration reloaded");
+                        if sender.send(settings).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => tracing::warn!("failed to reload configuration: {}", err),
+                }
+            }
+        });
+
+        Ok((
+            receiver,
+            ConfigWatcher {
+                _watcher: watcher,
+                task,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use serde::Deserialize;
+
+    use crate::configuration::{ConfigLoader, Validate, ValidationErrors};
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        gas_limit: u64,
+    }
+
+    impl Validate for Settings {
+        fn validate_at(&self, path: &str, errors: &mut ValidationErrors) {
+            if self.gas_limit == 0 {
+                errors.add(path, "gas_limit", "must be positive");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_are_published() {
+        let dir = std::env::temp_dir().join(format!("zkbob-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("base.yaml"), "gas_limit: 1\n").unwrap();
+
+        let (mut settings, _watcher) = ConfigLoader::new()
+            .dir(&dir)
+            .file("base")
+            .watch::<Settings>()
+            .unwrap();
+        assert_eq!(settings.borrow().gas_limit, 1);
+
+        // invalid settings are not published
+        fs::write(dir.join("base.yaml"), "gas_limit: 0\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        fs::write(dir.join("base.yaml"), "gas_limit: 2\n").unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), settings.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.borrow().gas_limit, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
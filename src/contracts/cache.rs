@@ -0,0 +1,388 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Instant,
+};
+
+use libzeropool::fawkes_crypto::ff_uint::Num;
+use web3::types::{H160, H256, U256, U64};
+
+use crate::{
+    configuration::{CachePolicy, CacheSettings},
+    Fr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum CacheKey {
+    PoolId,
+    ChainId,
+    DdQueueAddress,
+    PoolIndex,
+    Root(U256),
+    BlockTimestamp(U64),
+}
+
+impl CacheKey {
+    /// Values that can't change even if the chain reorgs.
+    fn is_chain_constant(&self) -> bool {
+        matches!(
+            self,
+            CacheKey::PoolId | CacheKey::ChainId | CacheKey::DdQueueAddress
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CacheValue {
+    U256(U256),
+    Address(H160),
+    Num(Num<Fr>),
+}
+
+pub(crate) trait Cacheable: Sized {
+    fn into_value(self) -> CacheValue;
+    fn from_value(value: CacheValue) -> Option<Self>;
+}
+
+impl Cacheable for U256 {
+    fn into_value(self) -> CacheValue {
+        CacheValue::U256(self)
+    }
+
+    fn from_value(value: CacheValue) -> Option<Self> {
+        match value {
+            CacheValue::U256(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Cacheable for H160 {
+    fn into_value(self) -> CacheValue {
+        CacheValue::Address(self)
+    }
+
+    fn from_value(value: CacheValue) -> Option<Self> {
+        match value {
+            CacheValue::Address(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Cacheable for Num<Fr> {
+    fn into_value(self) -> CacheValue {
+        CacheValue::Num(self)
+    }
+
+    fn from_value(value: CacheValue) -> Option<Self> {
+        match value {
+            CacheValue::Num(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Entry {
+    value: CacheValue,
+    expires_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<CacheKey, Entry>,
+    /// Insertion order used to evict the oldest entries once the cache is full.
+    order: VecDeque<CacheKey>,
+    /// Latest block seen by [`PoolCache::set_head`].
+    head: Option<(U64, H256)>,
+    /// Highest pool index read so far, kept regardless of the `pool_index` policy.
+    pool_index: Option<U256>,
+}
+
+/// Bounded read-through cache for `Pool` reads.
+///
+/// The cache doesn't watch the chain itself: reorgs are only detected by
+/// [`super::pool::Pool::check_reorg`], which has to be called periodically and
+/// also records the head used to tell final blocks apart.
+pub struct PoolCache {
+    settings: CacheSettings,
+    entries: Mutex<Entries>,
+}
+
+impl PoolCache {
+    pub fn new(settings: CacheSettings) -> Self {
+        Self {
+            settings,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops everything that may have changed, keeping only chain constants.
+    pub fn invalidate(&self) {
+        let mut entries = self.lock();
+        entries.map.retain(|key, _| key.is_chain_constant());
+        let Entries { map, order, .. } = &mut *entries;
+        order.retain(|key| map.contains_key(key));
+    }
+
+    /// Remembers the latest block so that a later check can detect it was replaced.
+    ///
+    /// Only compared by [`super::pool::Pool::check_reorg`], which sets it.
+    pub(crate) fn set_head(&self, number: U64, hash: H256) {
+        self.lock().head = Some((number, hash));
+    }
+
+    /// The block recorded by the last [`PoolCache::set_head`] call.
+    pub fn head(&self) -> Option<(U64, H256)> {
+        self.lock().head
+    }
+
+    pub(crate) fn get<V: Cacheable>(&self, key: CacheKey) -> Option<V> {
+        let mut entries = self.lock();
+        let expired = match entries.map.get(&key) {
+            Some(entry) => {
+                matches!(entry.expires_at, Some(expires_at) if expires_at <= Instant::now())
+            }
+            None => return None,
+        };
+        if expired {
+            entries.map.remove(&key);
+            entries.order.retain(|k| *k != key);
+            return None;
+        }
+        entries
+            .map
+            .get(&key)
+            .and_then(|entry| V::from_value(entry.value.clone()))
+    }
+
+    /// Caches a value read at the latest block.
+    pub(crate) fn insert<V: Cacheable>(&self, key: CacheKey, value: V) {
+        self.insert_at(key, value, None)
+    }
+
+    /// Caches a value read at `block`, the latest one if `None`. Values that
+    /// depend on the block are kept for at most [`CacheSettings::unfinalized`]
+    /// until they are final.
+    pub(crate) fn insert_at<V: Cacheable>(&self, key: CacheKey, value: V, block: Option<U64>) {
+        let expires_at = match self.policy(&key, block) {
+            CachePolicy::Disabled => return,
+            CachePolicy::Forever => None,
+            CachePolicy::Ttl(ttl) => Some(Instant::now() + ttl),
+        };
+
+        let mut entries = self.lock();
+        let entry = Entry {
+            value: value.into_value(),
+            expires_at,
+        };
+        if entries.map.insert(key, entry).is_none() {
+            entries.order.push_back(key);
+        }
+        while entries.map.len() > self.settings.max_entries {
+            match entries.order.pop_front() {
+                Some(oldest) => {
+                    entries.map.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Handles a `pool_index` read: a value lower than the cached one means the
+    /// chain was reorganized and roots above the new index are no longer valid.
+    pub(crate) fn observe_pool_index(&self, pool_index: U256) {
+        let previous = self.lock().pool_index.replace(pool_index);
+        if matches!(previous, Some(previous) if pool_index < previous) {
+            tracing::warn!(
+                "pool index went back to {}, invalidating pool cache",
+                pool_index
+            );
+            self.invalidate();
+        }
+        self.insert(CacheKey::PoolIndex, pool_index);
+    }
+
+    fn policy(&self, key: &CacheKey, block: Option<U64>) -> CachePolicy {
+        let policy = match key {
+            CacheKey::PoolId => self.settings.pool_id,
+            CacheKey::ChainId => self.settings.chain_id,
+            CacheKey::DdQueueAddress => self.settings.dd_queue_address,
+            CacheKey::PoolIndex => self.settings.pool_index,
+            CacheKey::Root(_) => self.settings.roots,
+            CacheKey::BlockTimestamp(_) => self.settings.block_timestamp,
+        };
+        let is_final = match (key, block) {
+            (CacheKey::PoolId | CacheKey::ChainId | CacheKey::DdQueueAddress, _) => return policy,
+            // short-lived anyway
+            (CacheKey::PoolIndex, _) => return policy,
+            (CacheKey::BlockTimestamp(number), _) => self.is_final(*number),
+            (CacheKey::Root(_), Some(block)) => self.is_final(block),
+            // a root read at the latest block is final once later transactions
+            // are built on it, reorging them lowers the pool index
+            (CacheKey::Root(index), None) => {
+                matches!(self.lock().pool_index, Some(pool_index) if *index < pool_index)
+            }
+        };
+        if is_final {
+            policy
+        } else {
+            shortest(policy, self.settings.unfinalized)
+        }
+    }
+
+    /// Whether `block` is at least [`CacheSettings::finality_depth`] blocks
+    /// behind the head recorded by [`PoolCache::set_head`].
+    fn is_final(&self, block: U64) -> bool {
+        match self.head() {
+            Some((head, _)) => {
+                block.as_u64().saturating_add(self.settings.finality_depth) <= head.as_u64()
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().expect("pool cache lock is poisoned")
+    }
+}
+
+fn shortest(a: CachePolicy, b: CachePolicy) -> CachePolicy {
+    match (a, b) {
+        (CachePolicy::Disabled, _) | (_, CachePolicy::Disabled) => CachePolicy::Disabled,
+        (CachePolicy::Forever, policy) | (policy, CachePolicy::Forever) => policy,
+        (CachePolicy::Ttl(a), CachePolicy::Ttl(b)) => CachePolicy::Ttl(a.min(b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use web3::types::{H256, U256, U64};
+
+    use crate::configuration::{CachePolicy, CacheSettings};
+
+    use super::{CacheKey, PoolCache};
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = PoolCache::new(CacheSettings {
+            pool_index: CachePolicy::Ttl(Duration::from_millis(10)),
+            ..Default::default()
+        });
+        cache.insert(CacheKey::PoolIndex, U256::from(128));
+        assert_eq!(
+            cache.get::<U256>(CacheKey::PoolIndex),
+            Some(U256::from(128))
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get::<U256>(CacheKey::PoolIndex), None);
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted() {
+        let cache = PoolCache::new(CacheSettings {
+            max_entries: 2,
+            ..Default::default()
+        });
+        for i in 0..3u64 {
+            cache.insert(CacheKey::Root(U256::from(i)), U256::from(i));
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get::<U256>(CacheKey::Root(U256::zero())), None);
+        assert!(cache.get::<U256>(CacheKey::Root(U256::from(2))).is_some());
+    }
+
+    #[test]
+    fn invalidate_keeps_only_chain_constants() {
+        let cache = PoolCache::new(CacheSettings::default());
+        cache.insert(CacheKey::ChainId, U256::from(137));
+        cache.insert(CacheKey::Root(U256::from(128)), U256::from(42));
+        cache.set_head(U64::from(10), H256::repeat_byte(1));
+
+        cache.invalidate();
+        assert_eq!(cache.get::<U256>(CacheKey::ChainId), Some(U256::from(137)));
+        assert_eq!(cache.get::<U256>(CacheKey::Root(U256::from(128))), None);
+        assert_eq!(cache.head(), Some((U64::from(10), H256::repeat_byte(1))));
+    }
+
+    #[test]
+    fn pool_index_rollback_invalidates_roots() {
+        let cache = PoolCache::new(CacheSettings::default());
+        cache.observe_pool_index(U256::from(256));
+        cache.insert(CacheKey::Root(U256::from(256)), U256::from(42));
+
+        cache.observe_pool_index(U256::from(128));
+        assert_eq!(cache.get::<U256>(CacheKey::Root(U256::from(256))), None);
+    }
+
+    #[test]
+    fn unfinalized_values_expire() {
+        let cache = PoolCache::new(CacheSettings {
+            finality_depth: 10,
+            unfinalized: CachePolicy::Disabled,
+            ..Default::default()
+        });
+        cache.insert(CacheKey::Root(U256::from(128)), U256::from(42));
+        cache.insert(CacheKey::BlockTimestamp(U64::from(95)), U256::from(1));
+        assert_eq!(cache.len(), 0);
+
+        cache.set_head(U64::from(100), H256::repeat_byte(1));
+        cache.insert(CacheKey::BlockTimestamp(U64::from(95)), U256::from(1));
+        cache.insert_at(
+            CacheKey::Root(U256::from(128)),
+            U256::from(42),
+            Some(U64::from(95)),
+        );
+        assert_eq!(cache.len(), 0);
+
+        cache.insert(CacheKey::BlockTimestamp(U64::from(90)), U256::from(1));
+        cache.insert_at(
+            CacheKey::Root(U256::from(128)),
+            U256::from(42),
+            Some(U64::from(90)),
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn roots_below_pool_index_are_final() {
+        let cache = PoolCache::new(CacheSettings {
+            unfinalized: CachePolicy::Ttl(Duration::from_millis(10)),
+            ..Default::default()
+        });
+        cache.observe_pool_index(U256::from(256));
+        cache.insert(CacheKey::Root(U256::from(128)), U256::from(41));
+        cache.insert(CacheKey::Root(U256::from(256)), U256::from(42));
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            cache.get::<U256>(CacheKey::Root(U256::from(128))),
+            Some(U256::from(41))
+        );
+        assert_eq!(cache.get::<U256>(CacheKey::Root(U256::from(256))), None);
+    }
+
+    #[test]
+    fn policy_from_string() {
+        assert_eq!(
+            CachePolicy::try_from("500ms".to_string()),
+            Ok(CachePolicy::Ttl(Duration::from_millis(500)))
+        );
+        assert_eq!(
+            CachePolicy::try_from("forever".to_string()),
+            Ok(CachePolicy::Forever)
+        );
+        assert!(CachePolicy::try_from("soon".to_string()).is_err());
+    }
+}
@@ -1,33 +1,637 @@
-use std::time::Duration;
+use std::{future::Future, time::Duration};
 
-use ethabi::ethereum_types::{H160};
+use ethabi::{ethereum_types::H160, LogParam, Token};
+use secp256k1::SecretKey;
 use tokio::time::timeout;
-use web3::{transports::Http, contract::{Contract, Options}, Web3};
+use tracing::field;
+use web3::{
+    contract::{Contract, Options},
+    transports::Http,
+    types::{BlockNumber, FilterBuilder, H256, U256},
+    Transport, Web3,
+};
 
-use super::error::PoolError;
+use crate::{
+    amount::{PoolAmount, TokenAmount},
+    metrics::{self, DD_CLIENT},
+    telemetry::span::traced,
+};
 
-pub struct DdContract {
-    pub contract: Contract<Http>,
+use super::{
+    error::PoolError,
+    events::{block_range, decode_logs, take_param, ContractEvent},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectDepositStatus {
+    Missing,
+    Pending,
+    Completed,
+    Refunded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZkAddress {
+    pub diversifier: [u8; 10],
+    pub pk: H256,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectDeposit {
+    pub fallback_receiver: H160,
+    pub sent: TokenAmount,
+    pub deposit: PoolAmount,
+    pub fee: PoolAmount,
+    pub timestamp: u64,
+    pub status: DirectDepositStatus,
+    pub zk_address: ZkAddress,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubmitDirectDeposit {
+    pub sender: H160,
+    pub nonce: U256,
+    pub fallback_user: H160,
+    pub zk_address: ZkAddress,
+    pub deposit: PoolAmount,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefundDirectDeposit {
+    pub nonce: U256,
+    pub receiver: H160,
+    pub amount: TokenAmount,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompleteDirectDepositBatch {
+    pub indices: Vec<U256>,
+}
+
+pub struct DdContract<T: Transport = Http> {
+    pub contract: Contract<T>,
+    web3: Web3<T>,
+    endpoint: Option<String>,
+    key: Option<SecretKey>,
+    gas_limit: Option<U256>,
     timeout: Duration,
 }
 
-impl DdContract {
-    pub fn new(address: H160, web3: Web3<Http>, timeout: Duration) -> Result<Self, PoolError> {
-        let contract = Contract::from_json(
-            web3.eth(),
-            address,
-            include_bytes!("dd-abi.json"),
-        )
-        .expect("failed to read contract");
+impl<T: Transport> DdContract<T> {
+    pub fn new(address: H160, web3: Web3<T>, timeout: Duration) -> Result<Self, PoolError> {
+        let contract = Contract::from_json(web3.eth(), address, include_bytes!("dd-abi.json"))?;
 
         Ok(Self {
             contract,
+            web3,
+            endpoint: None,
+            key: None,
+            gas_limit: None,
             timeout,
         })
     }
 
-    pub async fn fee(&self) -> Result<u64, PoolError> {
-        let result = self.contract.query("directDepositFee", (), None, Options::default(), None);
-        Ok(timeout(self.timeout, result).await??)
+    /// Sets the RPC endpoint label reported in spans, see [`crate::metrics::endpoint_label`].
+    pub fn with_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Sets the key used to sign deposit and refund transactions.
+    pub fn with_key(mut self, key: SecretKey, gas_limit: Option<U256>) -> Self {
+        self.key = Some(key);
+        self.gas_limit = gas_limit;
+        self
+    }
+
+    traced! {
+        fields(
+            contract = ?self.contract.address(),
+            endpoint = self.endpoint.as_ref().map(field::display)
+        );
+
+        #[span(name = "dd.fee")]
+        pub async fn fee(&self) -> Result<PoolAmount, PoolError> {
+            let result =
+                self.contract
+                    .query("directDepositFee", (), None, Options::default(), None);
+            let fee: u64 = self.rpc("eth_call", "directDepositFee", result).await?;
+            Ok(PoolAmount(fee))
+        }
+
+        #[span(name = "dd.nonce")]
+        pub async fn nonce(&self) -> Result<u32, PoolError> {
+            let result =
+                self.contract
+                    .query("directDepositNonce", (), None, Options::default(), None);
+            self.rpc("eth_call", "directDepositNonce", result).await
+        }
+
+        #[span(name = "dd.get_direct_deposit")]
+        pub async fn get_direct_deposit(&self, index: U256) -> Result<DirectDeposit, PoolError> {
+            let result =
+                self.contract
+                    .query("getDirectDeposit", (index,), None, Options::default(), None);
+            let deposit: Token = self.rpc("eth_call", "getDirectDeposit", result).await?;
+            parse_direct_deposit(deposit)
+        }
+
+        #[span(name = "dd.direct_deposit")]
+        pub async fn direct_deposit(
+            &self,
+            fallback_user: H160,
+            amount: TokenAmount,
+            zk_address: &str,
+        ) -> Result<H256, PoolError> {
+            self.signed_call(
+                "directDeposit",
+                (fallback_user, amount.0, zk_address.to_string()),
+                None,
+            )
+            .await
+        }
+
+        #[span(name = "dd.direct_native_deposit")]
+        pub async fn direct_native_deposit(
+            &self,
+            fallback_user: H160,
+            amount: TokenAmount,
+            zk_address: &str,
+        ) -> Result<H256, PoolError> {
+            self.signed_call(
+                "directNativeDeposit",
+                (fallback_user, zk_address.to_string()),
+                Some(amount.0),
+            )
+            .await
+        }
+
+        #[span(name = "dd.refund_direct_deposit")]
+        pub async fn refund_direct_deposit(&self, index: U256) -> Result<H256, PoolError> {
+            self.signed_call("refundDirectDeposit", (index,), None)
+                .await
+        }
+
+        #[span(
+            name = "dd.submit_events",
+            skip(from_block, to_block),
+            fields(block = %block_range(from_block, to_block)),
+        )]
+        pub async fn submit_events(
+            &self,
+            from_block: Option<BlockNumber>,
+            to_block: Option<BlockNumber>,
+            nonce: Option<U256>,
+        ) -> Result<Vec<ContractEvent<SubmitDirectDeposit>>, PoolError> {
+            // nonce is the second indexed parameter, after sender
+            let topic2 = nonce.map(|nonce| vec![u256_to_topic(nonce)]);
+            self.events(
+                "SubmitDirectDeposit",
+                from_block,
+                to_block,
+                None,
+                topic2,
+                |mut params| {
+                    Ok(SubmitDirectDeposit {
+                        sender: address(take_param(&mut params, "sender")?)?,
+                        nonce: uint(take_param(&mut params, "nonce")?)?,
+                        fallback_user: address(take_param(&mut params, "fallbackUser")?)?,
+                        zk_address: parse_zk_address(take_param(&mut params, "zkAddress")?)?,
+                        deposit: PoolAmount(uint64(take_param(&mut params, "deposit")?)?),
+                    })
+                },
+            )
+            .await
+        }
+
+        #[span(
+            name = "dd.refund_events",
+            skip(from_block, to_block),
+            fields(block = %block_range(from_block, to_block)),
+        )]
+        pub async fn refund_events(
+            &self,
+            from_block: Option<BlockNumber>,
+            to_block: Option<BlockNumber>,
+            nonce: Option<U256>,
+        ) -> Result<Vec<ContractEvent<RefundDirectDeposit>>, PoolError> {
+            let topic1 = nonce.map(|nonce| vec![u256_to_topic(nonce)]);
+            self.events(
+                "RefundDirectDeposit",
+                from_block,
+                to_block,
+                topic1,
+                None,
+                |mut params| {
+                    Ok(RefundDirectDeposit {
+                        nonce: uint(take_param(&mut params, "nonce")?)?,
+                        receiver: address(take_param(&mut params, "receiver")?)?,
+                        amount: TokenAmount(uint(take_param(&mut params, "amount")?)?),
+                    })
+                },
+            )
+            .await
+        }
+
+        #[span(
+            name = "dd.complete_batch_events",
+            skip(from_block, to_block),
+            fields(block = %block_range(from_block, to_block)),
+        )]
+        pub async fn complete_batch_events(
+            &self,
+            from_block: Option<BlockNumber>,
+            to_block: Option<BlockNumber>,
+        ) -> Result<Vec<ContractEvent<CompleteDirectDepositBatch>>, PoolError> {
+            self.events(
+                "CompleteDirectDepositBatch",
+                from_block,
+                to_block,
+                None,
+                None,
+                |mut params| {
+                    let indices = match take_param(&mut params, "indices")? {
+                        Token::Array(indices) => {
+                            indices.into_iter().map(uint).collect::<Result<_, _>>()?
+                        }
+                        token => return Err(unexpected_token("indices", &token)),
+                    };
+                    Ok(CompleteDirectDepositBatch { indices })
+                },
+            )
+            .await
+        }
+    }
+
+    async fn events<E, F>(
+        &self,
+        name: &str,
+        from_block: Option<BlockNumber>,
+        to_block: Option<BlockNumber>,
+        topic1: Option<Vec<H256>>,
+        topic2: Option<Vec<H256>>,
+        decode: F,
+    ) -> Result<Vec<ContractEvent<E>>, PoolError>
+    where
+        F: Fn(Vec<LogParam>) -> Result<E, PoolError>,
+    {
+        let event = self.contract.abi().event(name)?;
+        let filter = FilterBuilder::default()
+            .address(vec![self.contract.address()])
+            .topics(Some(vec![event.signature()]), topic1, topic2, None)
+            .from_block(from_block)
+            .to_block(to_block)
+            .build();
+
+        let logs = self
+            .rpc("eth_getLogs", "", self.web3.eth().logs(filter))
+            .await?;
+        decode_logs(event, logs, decode)
+    }
+
+    async fn signed_call<P: web3::contract::tokens::Tokenize>(
+        &self,
+        func: &str,
+        params: P,
+        value: Option<U256>,
+    ) -> Result<H256, PoolError> {
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| PoolError::GeneralError("secret key is not configured".to_string()))?;
+
+        let gas_price = self
+            .rpc("eth_gasPrice", "", self.web3.eth().gas_price())
+            .await?;
+        let options = Options {
+            gas: self.gas_limit,
+            gas_price: Some(gas_price),
+            value,
+            ..Default::default()
+        };
+
+        self.rpc(
+            "eth_sendRawTransaction",
+            func,
+            self.contract.signed_call(func, params, options, key),
+        )
+        .await
+    }
+
+    /// Runs an RPC call with the timeout, reporting it to [`metrics`] as `method`
+    /// calling the contract `function`.
+    async fn rpc<R, E, F>(&self, method: &str, function: &str, call: F) -> Result<R, PoolError>
+    where
+        F: Future<Output = Result<R, E>>,
+        PoolError: From<E>,
+    {
+        let endpoint = self.endpoint.as_deref().unwrap_or("unknown");
+        metrics::global()
+            .observe_function(DD_CLIENT, method, function, endpoint, async {
+                Ok(timeout(self.timeout, call).await??)
+            })
+            .await
+    }
+}
+
+fn parse_direct_deposit(token: Token) -> Result<DirectDeposit, PoolError> {
+    let fields = match token {
+        Token::Tuple(fields) if fields.len() == 8 => fields,
+        token => return Err(unexpected_token("DirectDeposit", &token)),
+    };
+    let mut fields = fields.into_iter();
+    let mut next = || fields.next().expect("tuple length is checked above");
+
+    let fallback_receiver = address(next())?;
+    let sent = TokenAmount(uint(next())?);
+    let deposit = PoolAmount(uint64(next())?);
+    let fee = PoolAmount(uint64(next())?);
+    let timestamp = uint64(next())?;
+    let status = match uint64(next())? {
+        0 => DirectDepositStatus::Missing,
+        1 => DirectDepositStatus::Pending,
+        2 => DirectDepositStatus::Completed,
+        3 => DirectDepositStatus::Refunded,
+        status => {
+            return Err(PoolError::GeneralError(format!(
+                "unknown direct deposit status {}",
+                status
+            )))
+        }
+    };
+    let diversifier = diversifier(next())?;
+    let pk = bytes32(next())?;
+
+    Ok(DirectDeposit {
+        fallback_receiver,
+        sent,
+        deposit,
+        fee,
+        timestamp,
+        status,
+        zk_address: ZkAddress { diversifier, pk },
+    })
+}
+
+fn parse_zk_address(token: Token) -> Result<ZkAddress, PoolError> {
+    match token {
+        Token::Tuple(fields) if fields.len() == 2 => {
+            let mut fields = fields.into_iter();
+            Ok(ZkAddress {
+                diversifier: diversifier(fields.next().unwrap())?,
+                pk: bytes32(fields.next().unwrap())?,
+            })
+        }
+        token => Err(unexpected_token("zkAddress", &token)),
+    }
+}
+
+fn address(token: Token) -> Result<H160, PoolError> {
+    match token {
+        Token::Address(address) => Ok(address),
+        token => Err(unexpected_token("address", &token)),
+    }
+}
+
+fn uint(token: Token) -> Result<U256, PoolError> {
+    match token {
+        Token::Uint(value) => Ok(value),
+        token => Err(unexpected_token("uint", &token)),
+    }
+}
+
+fn uint64(token: Token) -> Result<u64, PoolError> {
+    match token {
+        Token::Uint(value) if value <= U256::from(u64::MAX) => Ok(value.as_u64()),
+        token => Err(unexpected_token("uint64", &token)),
+    }
+}
+
+fn bytes32(token: Token) -> Result<H256, PoolError> {
+    match token {
+        Token::FixedBytes(bytes) if bytes.len() == 32 => Ok(H256::from_slice(&bytes)),
+        token => Err(unexpected_token("bytes32", &token)),
     }
-}
\ No newline at end of file
+}
+
+fn diversifier(token: Token) -> Result<[u8; 10], PoolError> {
+    match token {
+        Token::FixedBytes(bytes) if bytes.len() == 10 => {
+            let mut diversifier = [0; 10];
+            diversifier.copy_from_slice(&bytes);
+            Ok(diversifier)
+        }
+        token => Err(unexpected_token("bytes10", &token)),
+    }
+}
+
+fn u256_to_topic(n: U256) -> H256 {
+    let mut topic = [0; 32];
+    n.to_big_endian(&mut topic);
+    H256(topic)
+}
+
+fn unexpected_token(expected: &str, token: &Token) -> PoolError {
+    PoolError::GeneralError(format!("expected {}, got {:?}", expected, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethabi::Token;
+    use serde_json::Value;
+    use web3::{
+        types::{H160, H256, U256},
+        Web3,
+    };
+
+    use crate::amount::{PoolAmount, TokenAmount};
+
+    use super::{
+        super::mock::{self, MockTransport},
+        DdContract, DirectDepositStatus, PoolError,
+    };
+
+    fn dd_contract(transport: MockTransport) -> DdContract<MockTransport> {
+        DdContract::new(
+            H160::repeat_byte(0x02),
+            Web3::new(transport),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    fn abi() -> ethabi::Contract {
+        dd_contract(MockTransport::new(|_, _| None))
+            .contract
+            .abi()
+            .clone()
+    }
+
+    fn zk_address() -> Token {
+        Token::Tuple(vec![
+            Token::FixedBytes(vec![0x0d; 10]),
+            Token::FixedBytes(vec![0x0e; 32]),
+        ])
+    }
+
+    #[tokio::test]
+    async fn direct_deposit_is_parsed() {
+        let abi = abi();
+        let dd = dd_contract(MockTransport::calls(move |call| {
+            match call.function(&abi)? {
+                ("directDepositNonce", _) => Some(vec![Token::Uint(U256::from(7))]),
+                ("getDirectDeposit", args) if args == vec![Token::Uint(U256::from(3))] => {
+                    Some(vec![Token::Tuple(vec![
+                        Token::Address(H160::repeat_byte(0x0f)),
+                        Token::Uint(U256::exp10(18)),
+                        Token::Uint(U256::from(900_000_000u64)),
+                        Token::Uint(U256::from(100_000_000u64)),
+                        Token::Uint(U256::from(1_700_000_000u64)),
+                        Token::Uint(U256::from(3)),
+                        Token::FixedBytes(vec![0x0d; 10]),
+                        Token::FixedBytes(vec![0x0e; 32]),
+                    ])])
+                }
+                _ => None,
+            }
+        }));
+
+        assert_eq!(dd.nonce().await.unwrap(), 7);
+        let deposit = dd.get_direct_deposit(U256::from(3)).await.unwrap();
+        assert_eq!(deposit.fallback_receiver, H160::repeat_byte(0x0f));
+        assert_eq!(deposit.sent, TokenAmount(U256::exp10(18)));
+        assert_eq!(deposit.deposit, PoolAmount(900_000_000));
+        assert_eq!(deposit.fee, PoolAmount(100_000_000));
+        assert_eq!(deposit.timestamp, 1_700_000_000);
+        assert_eq!(deposit.status, DirectDepositStatus::Refunded);
+        assert_eq!(deposit.zk_address.diversifier, [0x0d; 10]);
+        assert_eq!(deposit.zk_address.pk, H256::repeat_byte(0x0e));
+    }
+
+    #[tokio::test]
+    async fn events_are_decoded() {
+        let abi = abi();
+        let submit = abi.event("SubmitDirectDeposit").unwrap().clone();
+        let refund = abi.event("RefundDirectDeposit").unwrap().clone();
+        let batch = abi.event("CompleteDirectDepositBatch").unwrap().clone();
+        let dd = dd_contract(MockTransport::new(move |method, params| {
+            if method != "eth_getLogs" {
+                return None;
+            }
+            let event = mock::filtered_event(params)?;
+            let log = if event == submit.signature() {
+                mock::log(
+                    &submit,
+                    vec![
+                        Token::Address(H160::repeat_byte(0x0a)),
+                        Token::Uint(U256::from(5)),
+                        Token::Address(H160::repeat_byte(0x0b)),
+                        zk_address(),
+                        Token::Uint(U256::from(50)),
+                    ],
+                    10,
+                    H256::repeat_byte(0x01),
+                )
+            } else if event == refund.signature() {
+                mock::log(
+                    &refund,
+                    vec![
+                        Token::Uint(U256::from(5)),
+                        Token::Address(H160::repeat_byte(0x0b)),
+                        Token::Uint(U256::from(60)),
+                    ],
+                    11,
+                    H256::repeat_byte(0x02),
+                )
+            } else if event == batch.signature() {
+                mock::log(
+                    &batch,
+                    vec![Token::Array(vec![
+                        Token::Uint(U256::from(4)),
+                        Token::Uint(U256::from(6)),
+                    ])],
+                    12,
+                    H256::repeat_byte(0x03),
+                )
+            } else {
+                return None;
+            };
+            Some(Value::Array(vec![log]))
+        }));
+
+        let submits = dd.submit_events(None, None, None).await.unwrap();
+        assert_eq!(submits.len(), 1);
+        let submit = &submits[0];
+        assert_eq!(submit.block_number.as_u64(), 10);
+        assert_eq!(submit.transaction_hash, H256::repeat_byte(0x01));
+        assert_eq!(submit.event.sender, H160::repeat_byte(0x0a));
+        assert_eq!(submit.event.nonce, U256::from(5));
+        assert_eq!(submit.event.fallback_user, H160::repeat_byte(0x0b));
+        assert_eq!(submit.event.zk_address.pk, H256::repeat_byte(0x0e));
+        assert_eq!(submit.event.deposit, PoolAmount(50));
+
+        let refunds = dd.refund_events(None, None, None).await.unwrap();
+        assert_eq!(refunds[0].event.nonce, U256::from(5));
+        assert_eq!(refunds[0].event.receiver, H160::repeat_byte(0x0b));
+        assert_eq!(refunds[0].event.amount, TokenAmount(U256::from(60)));
+
+        let batches = dd.complete_batch_events(None, None).await.unwrap();
+        assert_eq!(batches[0].event.indices, vec![U256::from(4), U256::from(6)]);
+    }
+
+    #[tokio::test]
+    async fn out_of_range_words_are_rejected() {
+        let submit = abi().event("SubmitDirectDeposit").unwrap().clone();
+        let too_large = Token::Uint(U256::from(u64::MAX) + 1);
+        let dd = dd_contract(MockTransport::new(move |method, _| match method {
+            "eth_call" => Some(mock::encode(&[Token::Tuple(vec![
+                Token::Address(H160::repeat_byte(0x0f)),
+                Token::Uint(U256::exp10(18)),
+                too_large.clone(),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::FixedBytes(vec![0x0d; 10]),
+                Token::FixedBytes(vec![0x0e; 32]),
+            ])])),
+            "eth_getLogs" => Some(Value::Array(vec![mock::log(
+                &submit,
+                vec![
+                    Token::Address(H160::repeat_byte(0x0a)),
+                    Token::Uint(U256::from(5)),
+                    Token::Address(H160::repeat_byte(0x0b)),
+                    zk_address(),
+                    too_large.clone(),
+                ],
+                10,
+                H256::repeat_byte(0x01),
+            )])),
+            _ => None,
+        }));
+
+        let err = dd.get_direct_deposit(U256::from(3)).await.unwrap_err();
+        assert!(matches!(err, PoolError::GeneralError(_)), "{:?}", err);
+        let err = dd.submit_events(None, None, None).await.unwrap_err();
+        assert!(matches!(err, PoolError::GeneralError(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn calls_are_metered() {
+        let dd = dd_contract(MockTransport::calls(|_| {
+            Some(vec![Token::Uint(U256::from(7))])
+        }))
+        .with_endpoint("http://dd.example".to_string());
+        dd.nonce().await.unwrap();
+
+        let text = crate::metrics::global().render().unwrap();
+        assert!(
+            text.contains(
+                r#"zkbob_client_requests_total{client="dd",endpoint="http://dd.example",function="directDepositNonce",method="eth_call"} 1"#
+            ),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use std::{cmp, collections::HashMap};
+
+use web3::{
+    transports::Http,
+    types::{BlockNumber, H256, U256, U64},
+    Transport,
+};
+
+use super::{
+    dd::{DdContract, DirectDepositStatus, RefundDirectDeposit, SubmitDirectDeposit},
+    error::PoolError,
+    events::ContractEvent,
+    pool::Pool,
+};
+
+#[derive(Debug, Clone)]
+pub enum DirectDepositState {
+    /// Submitted to the queue and waiting for the operator.
+    Pending,
+    /// Included into the pool as part of a direct deposit batch.
+    Completed(Inclusion),
+    /// Returned to the fallback receiver.
+    Refunded(ContractEvent<RefundDirectDeposit>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Inclusion {
+    /// Pool index of the `Message` that carries the batch.
+    pub pool_index: U256,
+    pub block_number: U64,
+    pub transaction_hash: H256,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectDepositRecord {
+    pub nonce: U256,
+    /// Missing when the deposit was submitted before the tracker's first synced block.
+    pub submission: Option<ContractEvent<SubmitDirectDeposit>>,
+    pub state: DirectDepositState,
+}
+
+/// Blocks behind the head the tracker syncs up to by default.
+pub const DEFAULT_CONFIRMATIONS: u64 = 12;
+
+/// Blocks fetched per `eth_getLogs` request by default.
+pub const DEFAULT_BLOCK_RANGE: u64 = 1000;
+
+/// Follows direct deposits from submission to pool inclusion or refund.
+///
+/// The tracker scans queue and pool events incrementally, so repeated calls to
+/// [`DirectDepositTracker::sync`] only fetch logs for blocks it has not seen yet.
+/// Only blocks with enough confirmations are synced, since events of reorged
+/// blocks are never revisited.
+pub struct DirectDepositTracker<T: Transport = Http> {
+    dd_contract: DdContract<T>,
+    deposits: HashMap<U256, DirectDepositRecord>,
+    next_block: U64,
+    confirmations: u64,
+    block_range: u64,
+}
+
+impl<T: Transport> DirectDepositTracker<T> {
+    pub fn new(dd_contract: DdContract<T>, start_block: U64) -> Self {
+        Self {
+            dd_contract,
+            deposits: HashMap::new(),
+            next_block: start_block,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            block_range: DEFAULT_BLOCK_RANGE,
+        }
+    }
+
+    /// Sets how many blocks behind the head syncing stops.
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Sets the number of blocks fetched per `eth_getLogs` request, at least one.
+    pub fn with_block_range(mut self, blocks: u64) -> Self {
+        self.block_range = blocks.max(1);
+        self
+    }
+
+    pub fn state(&self, nonce: U256) -> Option<&DirectDepositRecord> {
+        self.deposits.get(&nonce)
+    }
+
+    pub fn deposits(&self) -> impl Iterator<Item = &DirectDepositRecord> {
+        self.deposits.values()
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &DirectDepositRecord> {
+        self.deposits
+            .values()
+            .filter(|record| matches!(record.state, DirectDepositState::Pending))
+    }
+
+    /// Processes events up to the latest confirmed block and returns it.
+    ///
+    /// Logs are fetched in ranges of at most the configured block range, the
+    /// progress of completed ranges is kept if a later one fails.
+    pub async fn sync(&mut self, pool: &Pool<T>) -> Result<U64, PoolError> {
+        let head = pool.block_number().await?;
+        let to_block = head.saturating_sub(U64::from(self.confirmations));
+
+        while self.next_block <= to_block {
+            let chunk_end = cmp::min(
+                self.next_block
+                    .saturating_add(U64::from(self.block_range - 1)),
+                to_block,
+            );
+            self.sync_range(pool, self.next_block, chunk_end).await?;
+            self.next_block = chunk_end + U64::one();
+        }
+        Ok(to_block)
+    }
+
+    async fn sync_range(
+        &mut self,
+        pool: &Pool<T>,
+        from_block: U64,
+        to_block: U64,
+    ) -> Result<(), PoolError> {
+        let from = Some(BlockNumber::Number(from_block));
+        let to = Some(BlockNumber::Number(to_block));
+
+        for submission in self.dd_contract.submit_events(from, to, None).await? {
+            let nonce = submission.event.nonce;
+            self.deposits.insert(
+                nonce,
+                DirectDepositRecord {
+                    nonce,
+                    submission: Some(submission),
+                    state: DirectDepositState::Pending,
+                },
+            );
+        }
+
+        let batches = self.dd_contract.complete_batch_events(from, to).await?;
+        if !batches.is_empty() {
+            let messages = pool.message_events(from, to).await?;
+            for batch in batches {
+                let message = messages
+                    .iter()
+                    .find(|message| message.transaction_hash == batch.transaction_hash)
+                    .ok_or_else(|| {
+                        PoolError::RpcNodeInconsistency(format!(
+                            "no pool message in direct deposit batch tx {:?}",
+                            batch.transaction_hash
+                        ))
+                    })?;
+                let inclusion = Inclusion {
+                    pool_index: message.event.index,
+                    block_number: batch.block_number,
+                    transaction_hash: batch.transaction_hash,
+                };
+                for nonce in batch.event.indices {
+                    self.record(nonce).state = DirectDepositState::Completed(inclusion.clone());
+                }
+            }
+        }
+
+        for refund in self.dd_contract.refund_events(from, to, None).await? {
+            let nonce = refund.event.nonce;
+            self.record(nonce).state = DirectDepositState::Refunded(refund);
+        }
+        Ok(())
+    }
+
+    /// Resolves the state of a single deposit without relying on previously synced events.
+    ///
+    /// Logs are searched starting from `from_block`, which should not be later than
+    /// the block where the deposit was submitted.
+    pub async fn lookup(
+        pool: &Pool<T>,
+        dd_contract: &DdContract<T>,
+        nonce: U256,
+        from_block: BlockNumber,
+    ) -> Result<Option<DirectDepositRecord>, PoolError> {
+        let deposit = dd_contract.get_direct_deposit(nonce).await?;
+        let submission = dd_contract
+            .submit_events(Some(from_block), None, Some(nonce))
+            .await?
+            .into_iter()
+            .next();
+
+        let state = match deposit.status {
+            DirectDepositStatus::Missing => return Ok(None),
+            DirectDepositStatus::Pending => DirectDepositState::Pending,
+            DirectDepositStatus::Refunded => {
+                let refund = dd_contract
+                    .refund_events(Some(from_block), None, Some(nonce))
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        PoolError::RpcNodeInconsistency(format!(
+                            "refund event for direct deposit {} not found",
+                            nonce
+                        ))
+                    })?;
+                DirectDepositState::Refunded(refund)
+            }
+            DirectDepositStatus::Completed => {
+                let batch = dd_contract
+                    .complete_batch_events(Some(from_block), None)
+                    .await?
+                    .into_iter()
+                    .find(|batch| batch.event.indices.contains(&nonce))
+                    .ok_or_else(|| {
+                        PoolError::RpcNodeInconsistency(format!(
+                            "batch with direct deposit {} not found",
+                            nonce
+                        ))
+                    })?;
+                let block = Some(BlockNumber::Number(batch.block_number));
+                let message = pool
+                    .message_events(block, block)
+                    .await?
+                    .into_iter()
+                    .find(|message| message.transaction_hash == batch.transaction_hash)
+                    .ok_or_else(|| {
+                        PoolError::RpcNodeInconsistency(format!(
+                            "no pool message in direct deposit batch tx {:?}",
+                            batch.transaction_hash
+                        ))
+                    })?;
+                DirectDepositState::Completed(Inclusion {
+                    pool_index: message.event.index,
+                    block_number: batch.block_number,
+                    transaction_hash: batch.transaction_hash,
+                })
+            }
+        };
+
+        Ok(Some(DirectDepositRecord {
+            nonce,
+            submission,
+            state,
+        }))
+    }
+
+    fn record(&mut self, nonce: U256) -> &mut DirectDepositRecord {
+        self.deposits
+            .entry(nonce)
+            .or_insert_with(|| DirectDepositRecord {
+                nonce,
+                submission: None,
+                state: DirectDepositState::Pending,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use ethabi::Token;
+    use serde_json::{json, Value};
+    use web3::{
+        types::{H160, H256, U256, U64},
+        Web3,
+    };
+
+    use super::{
+        super::{
+            dd::DdContract,
+            mock::{self, MockTransport},
+        },
+        DirectDepositState, DirectDepositTracker,
+    };
+
+    const BATCH_TX: H256 = H256([0x03; 32]);
+
+    /// Chain at block 32 with deposits 1 and 2 submitted, 1 completed in a batch
+    /// at pool index 256 and 2 refunded.
+    fn chain() -> MockTransport {
+        let transport = MockTransport::new(|_, _| None);
+        let pool_abi = mock::pool(transport.clone()).contract.abi().clone();
+        let dd_abi = DdContract::new(H160::zero(), Web3::new(transport), Duration::ZERO)
+            .unwrap()
+            .contract
+            .abi()
+            .clone();
+        let submit = dd_abi.event("SubmitDirectDeposit").unwrap().clone();
+        let refund = dd_abi.event("RefundDirectDeposit").unwrap().clone();
+        let batch = dd_abi.event("CompleteDirectDepositBatch").unwrap().clone();
+        let message = pool_abi.event("Message").unwrap().clone();
+
+        let submit_log = |nonce: u64| {
+            mock::log(
+                &submit,
+                vec![
+                    Token::Address(H160::repeat_byte(0x0a)),
+                    Token::Uint(U256::from(nonce)),
+                    Token::Address(H160::repeat_byte(0x0b)),
+                    Token::Tuple(vec![
+                        Token::FixedBytes(vec![0; 10]),
+                        Token::FixedBytes(vec![0; 32]),
+                    ]),
+                    Token::Uint(U256::from(50)),
+                ],
+                10,
+                H256::repeat_byte(nonce as u8),
+            )
+        };
+        let logs = [
+            (submit.signature(), submit_log(1)),
+            (submit.signature(), submit_log(2)),
+            (
+                batch.signature(),
+                mock::log(
+                    &batch,
+                    vec![Token::Array(vec![Token::Uint(U256::from(1))])],
+                    12,
+                    BATCH_TX,
+                ),
+            ),
+            (
+                message.signature(),
+                mock::log(
+                    &message,
+                    vec![
+                        Token::Uint(U256::from(256)),
+                        Token::FixedBytes(vec![0; 32]),
+                        Token::Bytes(vec![1, 2, 3]),
+                    ],
+                    12,
+                    BATCH_TX,
+                ),
+            ),
+            (
+                refund.signature(),
+                mock::log(
+                    &refund,
+                    vec![
+                        Token::Uint(U256::from(2)),
+                        Token::Address(H160::repeat_byte(0x0b)),
+                        Token::Uint(U256::from(60)),
+                    ],
+                    15,
+                    H256::repeat_byte(0x04),
+                ),
+            ),
+        ];
+
+        MockTransport::new(move |method, params| match method {
+            "eth_blockNumber" => Some(json!("0x20")),
+            "eth_getLogs" => {
+                let event = mock::filtered_event(params)?;
+                let (from, to) = block_range(params);
+                Some(Value::Array(
+                    logs.iter()
+                        .filter(|(signature, log)| {
+                            let block = block_number(&log["blockNumber"]);
+                            *signature == event && from <= block && block <= to
+                        })
+                        .map(|(_, log)| log.clone())
+                        .collect(),
+                ))
+            }
+            _ => None,
+        })
+    }
+
+    fn block_number(block: &Value) -> u64 {
+        u64::from_str_radix(block.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+    }
+
+    /// Blocks an `eth_getLogs` request filters for.
+    fn block_range(params: &[Value]) -> (u64, u64) {
+        let filter = &params[0];
+        let block = |key| filter.get(key).map(block_number);
+        (
+            block("fromBlock").unwrap_or(0),
+            block("toBlock").unwrap_or(u64::MAX),
+        )
+    }
+
+    fn tracker(transport: MockTransport) -> DirectDepositTracker<MockTransport> {
+        let dd_contract = DdContract::new(
+            H160::repeat_byte(0x02),
+            Web3::new(transport),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        DirectDepositTracker::new(dd_contract, U64::from(1))
+    }
+
+    async fn synced_tracker() -> DirectDepositTracker<MockTransport> {
+        let transport = chain();
+        let pool = mock::pool(transport.clone());
+        let mut tracker = tracker(transport);
+        assert_eq!(tracker.sync(&pool).await.unwrap(), U64::from(20));
+        tracker
+    }
+
+    #[tokio::test]
+    async fn submitted_deposit_is_completed() {
+        let tracker = synced_tracker().await;
+        let record = tracker.state(U256::from(1)).unwrap();
+        assert!(record.submission.is_some());
+        match &record.state {
+            DirectDepositState::Completed(inclusion) => {
+                assert_eq!(inclusion.pool_index, U256::from(256));
+                assert_eq!(inclusion.block_number, U64::from(12));
+                assert_eq!(inclusion.transaction_hash, BATCH_TX);
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
+    }
+
+    #[tokio::test]
+    async fn submitted_deposit_is_refunded() {
+        let tracker = synced_tracker().await;
+        let record = tracker.state(U256::from(2)).unwrap();
+        assert!(record.submission.is_some());
+        match &record.state {
+            DirectDepositState::Refunded(refund) => {
+                assert_eq!(refund.event.amount.0, U256::from(60));
+                assert_eq!(refund.block_number, U64::from(15));
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
+        assert_eq!(tracker.pending().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn unknown_nonce_has_no_state() {
+        let tracker = synced_tracker().await;
+        assert!(tracker.state(U256::from(3)).is_none());
+        assert_eq!(tracker.deposits().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn sync_pages_confirmed_blocks() {
+        let chain = chain();
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let transport = MockTransport::new({
+            let ranges = ranges.clone();
+            move |method, params| {
+                if method == "eth_getLogs" {
+                    ranges.lock().unwrap().push(block_range(params));
+                }
+                chain.answer(method, params)
+            }
+        });
+        let pool = mock::pool(transport.clone());
+        let mut tracker = tracker(transport)
+            .with_confirmations(20)
+            .with_block_range(8);
+
+        assert_eq!(tracker.sync(&pool).await.unwrap(), U64::from(12));
+        assert_eq!(tracker.sync(&pool).await.unwrap(), U64::from(12));
+
+        let mut ranges = ranges.lock().unwrap().clone();
+        ranges.dedup();
+        assert_eq!(ranges, vec![(1, 8), (9, 12)]);
+        assert!(matches!(
+            tracker.state(U256::from(1)).unwrap().state,
+            DirectDepositState::Completed(_)
+        ));
+        // the refund at block 15 isn't confirmed yet
+        assert_eq!(tracker.pending().count(), 1);
+    }
+}
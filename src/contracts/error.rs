@@ -1,6 +1,6 @@
-use strum::Display;
+use strum::{Display, IntoStaticStr};
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, IntoStaticStr)]
 pub enum PoolError {
     BadAbi(std::io::Error),
     GeneralError(String),
@@ -9,6 +9,7 @@ pub enum PoolError {
     Web3Error(web3::Error),
     RequestTimeout(tokio::time::error::Elapsed),
     RpcNodeInconsistency(String),
+    CallReverted(Vec<u8>),
 }
 
 impl From<std::io::Error> for PoolError {
@@ -23,6 +24,12 @@ impl From<web3::contract::Error> for PoolError {
     }
 }
 
+impl From<ethabi::Error> for PoolError {
+    fn from(e: ethabi::Error) -> Self {
+        PoolError::ContractException(e.into())
+    }
+}
+
 impl From<web3::Error> for PoolError {
     fn from(e: web3::Error) -> Self {
         PoolError::Web3Error(e)
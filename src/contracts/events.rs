@@ -0,0 +1,83 @@
+use ethabi::{Event, LogParam, RawLog, Token};
+use web3::types::{BlockId, BlockNumber, Log, H256, U256, U64};
+
+use super::error::PoolError;
+
+/// Decoded contract event together with the position of its log on chain.
+#[derive(Debug, Clone)]
+pub struct ContractEvent<E> {
+    pub event: E,
+    pub block_number: U64,
+    pub transaction_hash: H256,
+    pub log_index: U256,
+}
+
+pub(crate) fn decode_logs<E, F>(
+    event: &Event,
+    logs: Vec<Log>,
+    decode: F,
+) -> Result<Vec<ContractEvent<E>>, PoolError>
+where
+    F: Fn(Vec<LogParam>) -> Result<E, PoolError>,
+{
+    logs.into_iter()
+        .filter(|log| !log.is_removed())
+        .map(|log| {
+            let (block_number, transaction_hash, log_index) =
+                match (log.block_number, log.transaction_hash, log.log_index) {
+                    (Some(block_number), Some(transaction_hash), Some(log_index)) => {
+                        (block_number, transaction_hash, log_index)
+                    }
+                    _ => {
+                        return Err(PoolError::GeneralError(format!(
+                            "pending {} log can't be decoded",
+                            event.name
+                        )))
+                    }
+                };
+
+            let parsed = event.parse_log(RawLog {
+                topics: log.topics,
+                data: log.data.0,
+            })?;
+
+            Ok(ContractEvent {
+                event: decode(parsed.params)?,
+                block_number,
+                transaction_hash,
+                log_index,
+            })
+        })
+        .collect()
+}
+
+/// Formats an event query range for span fields, e.g. `100..latest`.
+pub(crate) fn block_range(
+    from_block: Option<BlockNumber>,
+    to_block: Option<BlockNumber>,
+) -> String {
+    format!("{}..{}", block_label(from_block), block_label(to_block))
+}
+
+pub(crate) fn block_id_label(block: BlockId) -> String {
+    match block {
+        BlockId::Hash(hash) => format!("{:?}", hash),
+        BlockId::Number(number) => block_label(Some(number)),
+    }
+}
+
+pub(crate) fn block_label(block: Option<BlockNumber>) -> String {
+    match block {
+        Some(BlockNumber::Number(number)) => number.to_string(),
+        Some(block) => format!("{:?}", block).to_lowercase(),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn take_param(params: &mut Vec<LogParam>, name: &str) -> Result<Token, PoolError> {
+    params
+        .iter()
+        .position(|param| param.name == name)
+        .map(|position| params.swap_remove(position).value)
+        .ok_or_else(|| PoolError::GeneralError(format!("missing event param {}", name)))
+}
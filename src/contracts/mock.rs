@@ -0,0 +1,227 @@
+//! In-memory transport for contract client tests.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ethabi::Token;
+use futures::future::{self, Ready};
+use jsonrpc_core::{Call, Error as RpcError, Value};
+use serde_json::json;
+use web3::{
+    helpers,
+    types::{Bytes, H160, H256, U256, U64},
+    Error as Web3Error, RequestId, Transport,
+};
+
+use crate::configuration::Web3Settings;
+
+use super::pool::Pool;
+
+/// Return data of calls reverted by [`MockTransport::multicall`].
+pub const REVERT_DATA: &[u8] = b"reverted";
+
+type Handler = dyn Fn(&str, &[Value]) -> Option<Value> + Send + Sync;
+
+/// Transport answering requests with `handler(method, params)`, failing the
+/// ones it returns `None` for.
+#[derive(Clone)]
+pub struct MockTransport {
+    handler: Arc<Handler>,
+    methods: Arc<Mutex<Vec<String>>>,
+    id: Arc<AtomicUsize>,
+}
+
+impl MockTransport {
+    pub fn new(handler: impl Fn(&str, &[Value]) -> Option<Value> + Send + Sync + 'static) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            methods: Default::default(),
+            id: Default::default(),
+        }
+    }
+
+    /// Transport answering `eth_call`s with `handler(call)`.
+    pub fn calls(handler: impl Fn(&EthCall) -> Option<Vec<Token>> + Send + Sync + 'static) -> Self {
+        Self::new(move |method, params| match method {
+            "eth_call" => handler(&EthCall::parse(params)?).map(|tokens| encode(&tokens)),
+            _ => None,
+        })
+    }
+
+    /// Transport answering Multicall3 `aggregate3` calls, with `handler(target, call)`
+    /// answering each aggregated call and its `None` reverting the call with
+    /// [`REVERT_DATA`].
+    pub fn multicall(
+        handler: impl Fn(H160, &EthCall) -> Option<Vec<Token>> + Send + Sync + 'static,
+    ) -> Self {
+        let abi = ethabi::Contract::load(&include_bytes!("multicall-abi.json")[..]).unwrap();
+        Self::calls(move |call| {
+            let calls = match call.function(&abi)? {
+                ("aggregate3", mut args) if args.len() == 1 => match args.remove(0) {
+                    Token::Array(calls) => calls,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let results = calls
+                .into_iter()
+                .map(|item| match item {
+                    Token::Tuple(fields) => match fields.as_slice() {
+                        [Token::Address(target), Token::Bool(_), Token::Bytes(data)] => {
+                            let item = EthCall {
+                                data: data.clone(),
+                                block: call.block.clone(),
+                            };
+                            Some(match handler(*target, &item) {
+                                Some(tokens) => Token::Tuple(vec![
+                                    Token::Bool(true),
+                                    Token::Bytes(ethabi::encode(&tokens)),
+                                ]),
+                                None => Token::Tuple(vec![
+                                    Token::Bool(false),
+                                    Token::Bytes(REVERT_DATA.to_vec()),
+                                ]),
+                            })
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(vec![Token::Array(results)])
+        })
+    }
+
+    /// Answer of the handler, for handlers extending this one.
+    pub fn answer(&self, method: &str, params: &[Value]) -> Option<Value> {
+        (self.handler)(method, params)
+    }
+
+    /// Methods of the requests sent so far.
+    pub fn methods(&self) -> Vec<String> {
+        self.methods.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockTransport").finish_non_exhaustive()
+    }
+}
+
+impl Transport for MockTransport {
+    type Out = Ready<web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        let (method, params) = match request {
+            Call::MethodCall(call) => (call.method, call.params.into()),
+            _ => return future::ready(Err(Web3Error::Internal)),
+        };
+        let params = match params {
+            Value::Array(params) => params,
+            _ => Vec::new(),
+        };
+        let result = (self.handler)(&method, &params)
+            .ok_or_else(|| Web3Error::Rpc(RpcError::method_not_found()));
+        self.methods.lock().unwrap().push(method);
+        future::ready(result)
+    }
+}
+
+/// Decoded `eth_call` request.
+#[derive(Debug)]
+pub struct EthCall {
+    pub data: Vec<u8>,
+    /// Block parameter as sent, e.g. `latest` or `0x10`.
+    pub block: String,
+}
+
+impl EthCall {
+    fn parse(params: &[Value]) -> Option<Self> {
+        let data: Bytes = serde_json::from_value(params.first()?.get("data")?.clone()).ok()?;
+        let block = match params.get(1) {
+            Some(Value::String(block)) => block.clone(),
+            Some(Value::Object(block)) => block.values().next()?.as_str()?.to_string(),
+            _ => "latest".to_string(),
+        };
+        Some(Self {
+            data: data.0,
+            block,
+        })
+    }
+
+    /// Name and arguments of the called `abi` function.
+    pub fn function<'a>(&self, abi: &'a ethabi::Contract) -> Option<(&'a str, Vec<Token>)> {
+        let (selector, input) = (self.data.get(..4)?, &self.data[4..]);
+        let function = abi
+            .functions()
+            .find(|function| function.short_signature() == selector)?;
+        Some((&function.name, function.decode_input(input).ok()?))
+    }
+}
+
+/// ABI-encodes `tokens` as an `eth_call` result.
+pub fn encode(tokens: &[Token]) -> Value {
+    serde_json::to_value(Bytes(ethabi::encode(tokens))).unwrap()
+}
+
+/// Mined log of `event` with `params` given in declaration order.
+pub fn log(event: &ethabi::Event, params: Vec<Token>, block: u64, tx: H256) -> Value {
+    let mut topics = vec![event.signature()];
+    let mut data = Vec::new();
+    for (input, param) in event.inputs.iter().zip(params) {
+        if input.indexed {
+            topics.push(H256::from_slice(&ethabi::encode(&[param])));
+        } else {
+            data.push(param);
+        }
+    }
+    json!({
+        "address": H160::zero(),
+        "topics": topics,
+        "data": Bytes(ethabi::encode(&data)),
+        "blockHash": H256::zero(),
+        "blockNumber": U64::from(block),
+        "transactionHash": tx,
+        "transactionIndex": U64::zero(),
+        "logIndex": U256::zero(),
+        "transactionLogIndex": U256::zero(),
+        "removed": false,
+    })
+}
+
+/// Event signature an `eth_getLogs` request filters for.
+pub fn filtered_event(params: &[Value]) -> Option<H256> {
+    let topic = match params.first()?.get("topics")?.get(0)? {
+        Value::Array(topics) => topics.first()?,
+        topic => topic,
+    };
+    serde_json::from_value(topic.clone()).ok()
+}
+
+/// Settings of a pool at `0x0101..01` without cache and key.
+pub fn settings() -> Web3Settings {
+    Web3Settings {
+        provider_endpoint: "http://localhost:8545".to_string(),
+        provider_timeout_sec: 5,
+        pool_address: format!("{:?}", H160::repeat_byte(0x01)),
+        gas_limit: None,
+        multicall_address: None,
+        secret_key: None,
+        cache: None,
+    }
+}
+
+pub fn pool(transport: MockTransport) -> Pool<MockTransport> {
+    Pool::with_transport(&settings(), transport).unwrap()
+}
@@ -1,3 +1,11 @@
 pub mod error;
 pub mod pool;
 pub mod dd;
+pub mod dd_tracker;
+pub mod events;
+pub mod multicall;
+pub mod token;
+pub mod cache;
+pub mod pinned;
+#[cfg(test)]
+mod mock;
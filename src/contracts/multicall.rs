@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use ethabi::{ethereum_types::H160, Token};
+use tokio::time::timeout;
+use web3::{
+    contract::{Contract, Options},
+    transports::Http,
+    types::BlockId,
+    Transport, Web3,
+};
+
+use super::error::PoolError;
+
+/// Multicall3 is deployed at the same address on most EVM chains.
+pub const MULTICALL3_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+/// Maximum number of calls aggregated into a single `eth_call`.
+pub const MAX_CALLS_PER_BATCH: usize = 500;
+
+pub struct Multicall<T: Transport = Http> {
+    pub contract: Contract<T>,
+    timeout: Duration,
+}
+
+impl<T: Transport> Multicall<T> {
+    pub fn new(address: H160, web3: Web3<T>, timeout: Duration) -> Result<Self, PoolError> {
+        let contract =
+            Contract::from_json(web3.eth(), address, include_bytes!("multicall-abi.json"))?;
+
+        Ok(Self { contract, timeout })
+    }
+
+    /// Executes `calls` as `(target, calldata)` pairs at `block` (latest if `None`)
+    /// and returns their raw return data.
+    ///
+    /// Results are in the same order as `calls`. A reverted call doesn't fail the
+    /// whole batch and is reported as [`PoolError::CallReverted`] in its slot.
+    pub async fn aggregate(
+        &self,
+        calls: Vec<(H160, Vec<u8>)>,
+        block: Option<BlockId>,
+    ) -> Result<Vec<Result<Vec<u8>, PoolError>>, PoolError> {
+        let mut results = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(MAX_CALLS_PER_BATCH) {
+            let calls = chunk
+                .iter()
+                .map(|(target, call_data)| {
+                    Token::Tuple(vec![
+                        Token::Address(*target),
+                        Token::Bool(true),
+                        Token::Bytes(call_data.clone()),
+                    ])
+                })
+                .collect();
+
+            let result = self.contract.query(
+                "aggregate3",
+                (Token::Array(calls),),
+                None,
+                Options::default(),
+                block,
+            );
+            let output: Token = timeout(self.timeout, result).await??;
+
+            let output = match output {
+                Token::Array(output) if output.len() == chunk.len() => output,
+                _ => {
+                    return Err(PoolError::RpcNodeInconsistency(
+                        "unexpected multicall response".to_string(),
+                    ))
+                }
+            };
+            for item in output {
+                results.push(match item {
+                    Token::Tuple(item) => match item.as_slice() {
+                        [Token::Bool(true), Token::Bytes(data)] => Ok(data.clone()),
+                        [Token::Bool(false), Token::Bytes(data)] => {
+                            Err(PoolError::CallReverted(data.clone()))
+                        }
+                        _ => {
+                            return Err(PoolError::RpcNodeInconsistency(
+                                "unexpected multicall result".to_string(),
+                            ))
+                        }
+                    },
+                    _ => {
+                        return Err(PoolError::RpcNodeInconsistency(
+                            "unexpected multicall result".to_string(),
+                        ))
+                    }
+                });
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethabi::Token;
+    use web3::{
+        types::{H160, U256},
+        Web3,
+    };
+
+    use super::{
+        super::{
+            error::PoolError,
+            mock::{MockTransport, REVERT_DATA},
+        },
+        Multicall, MAX_CALLS_PER_BATCH,
+    };
+
+    fn multicall(transport: MockTransport) -> Multicall<MockTransport> {
+        Multicall::new(
+            H160::repeat_byte(0xca),
+            Web3::new(transport),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    /// Multicall echoing the calldata of each call as `uint256`, reverting calls
+    /// to the zero address.
+    fn echo() -> MockTransport {
+        MockTransport::multicall(|target, call| {
+            if target.is_zero() {
+                return None;
+            }
+            Some(vec![Token::Uint(U256::from_big_endian(&call.data))])
+        })
+    }
+
+    fn calls(count: usize) -> Vec<(H160, Vec<u8>)> {
+        (0..count)
+            .map(|i| (H160::repeat_byte(0x01), (i as u32).to_be_bytes().to_vec()))
+            .collect()
+    }
+
+    fn uint(result: &Result<Vec<u8>, PoolError>) -> U256 {
+        U256::from_big_endian(result.as_ref().unwrap())
+    }
+
+    #[tokio::test]
+    async fn results_are_decoded_in_order() {
+        let multicall = multicall(echo());
+        let results = multicall
+            .aggregate(
+                vec![
+                    (H160::repeat_byte(0x01), vec![7]),
+                    (H160::zero(), vec![8]),
+                    (H160::repeat_byte(0x02), vec![9]),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(uint(&results[0]), U256::from(7));
+        match &results[1] {
+            Err(PoolError::CallReverted(data)) => assert_eq!(data, REVERT_DATA),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(uint(&results[2]), U256::from(9));
+    }
+
+    #[tokio::test]
+    async fn calls_are_chunked() {
+        for (count, batches) in [
+            (MAX_CALLS_PER_BATCH, 1),
+            (MAX_CALLS_PER_BATCH + 1, 2),
+            (0, 0),
+        ] {
+            let transport = echo();
+            let results = multicall(transport.clone())
+                .aggregate(calls(count), None)
+                .await
+                .unwrap();
+
+            assert_eq!(transport.methods().len(), batches);
+            assert_eq!(results.len(), count);
+            for (i, result) in results.iter().enumerate() {
+                assert_eq!(uint(result), U256::from(i));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn short_response_is_inconsistent() {
+        let transport = MockTransport::calls(|_| Some(vec![Token::Array(Vec::new())]));
+        let err = multicall(transport)
+            .aggregate(calls(2), None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, PoolError::RpcNodeInconsistency(_)),
+            "{:?}",
+            err
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use libzeropool::fawkes_crypto::{engines::bn256::Fr, ff_uint::Num};
+use web3::{
+    contract::tokens::Detokenize,
+    types::{BlockId, H160, H256, U256, U64},
+    Transport,
+};
+
+use crate::telemetry::span::traced;
+
+use super::{
+    error::PoolError,
+    pool::{num_to_u256, u256_to_num, Pool, PoolInfo},
+};
+
+type Roots = Vec<Result<Num<Fr>, PoolError>>;
+
+/// Result of a read together with the block it was made at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtBlock<V> {
+    pub block_number: U64,
+    pub value: V,
+}
+
+impl<V> AtBlock<V> {
+    pub fn map<U>(self, f: impl FnOnce(V) -> U) -> AtBlock<U> {
+        AtBlock {
+            block_number: self.block_number,
+            value: f(self.value),
+        }
+    }
+}
+
+/// Pool reads pinned to a single block, created with [`Pool::at`].
+///
+/// Results of several calls are guaranteed to be consistent with each other.
+/// Reads bypass the read-through cache, final roots are still added to it.
+pub struct PinnedPool<'a, T: Transport> {
+    pool: &'a Pool<T>,
+    block: BlockId,
+    block_number: U64,
+}
+
+impl<'a, T: Transport> PinnedPool<'a, T> {
+    pub(super) fn new(pool: &'a Pool<T>, block: BlockId, block_number: U64) -> Self {
+        Self {
+            pool,
+            block,
+            block_number,
+        }
+    }
+
+    pub fn block(&self) -> BlockId {
+        self.block
+    }
+
+    pub fn block_number(&self) -> U64 {
+        self.block_number
+    }
+
+    traced! {
+        fields(
+            contract = ?self.pool.contract.address(),
+            endpoint = %self.pool.endpoint(),
+            block = %self.block_number
+        );
+
+        #[span(name = "pinned_pool.nullifier_exists")]
+        pub async fn nullifier_exists(
+            &self,
+            nullifier: Num<Fr>,
+        ) -> Result<AtBlock<bool>, PoolError> {
+            let nullifier = num_to_u256(nullifier);
+            let exists: U256 = self
+                .pool
+                .query_with("nullifiers", (nullifier,), Some(self.block))
+                .await?;
+            Ok(self.wrap(!exists.is_zero()))
+        }
+
+        /// Checks many nullifiers with Multicall3, preserving the order of `nullifiers`.
+        #[span(name = "pinned_pool.nullifiers_exist", skip(nullifiers))]
+        pub async fn nullifiers_exist(
+            &self,
+            nullifiers: &[Num<Fr>],
+        ) -> Result<AtBlock<Vec<Result<bool, PoolError>>>, PoolError> {
+            let args = nullifiers
+                .iter()
+                .map(|n| num_to_u256(*n))
+                .collect::<Vec<_>>();
+            let results = self
+                .pool
+                .multicall_u256("nullifiers", &args, Some(self.block))
+                .await?;
+            Ok(self.wrap(
+                results
+                    .into_iter()
+                    .map(|exists| exists.map(|exists| !exists.is_zero()))
+                    .collect(),
+            ))
+        }
+
+        /// Reads many `roots` entries with Multicall3, preserving the order of `indices`.
+        #[span(name = "pinned_pool.roots_by_indices", skip(indices))]
+        pub async fn roots_by_indices(
+            &self,
+            indices: &[U256],
+        ) -> Result<AtBlock<Roots>, PoolError> {
+            let results = self
+                .pool
+                .multicall_u256("roots", indices, Some(self.block))
+                .await?;
+            Ok(self.wrap(
+                results
+                    .into_iter()
+                    .map(|root| {
+                        root.and_then(|root| {
+                            u256_to_num(root)
+                                .ok_or(PoolError::GeneralError("failed to parse root".to_string()))
+                        })
+                    })
+                    .collect(),
+            ))
+        }
+
+        #[span(name = "pinned_pool.root_by_index")]
+        pub async fn root_by_index(&self, index: Num<Fr>) -> Result<AtBlock<Num<Fr>>, PoolError> {
+            let root = self
+                .pool
+                .root_u256(num_to_u256(index), Some(self.pinned()))
+                .await?;
+            let root = u256_to_num(root)
+                .ok_or(PoolError::GeneralError("failed to parse root".to_string()))?;
+            Ok(self.wrap(root))
+        }
+
+        #[span(name = "pinned_pool.pool_id")]
+        pub async fn pool_id(&self) -> Result<AtBlock<Num<Fr>>, PoolError> {
+            let pool_id = self.query_value("pool_id").await?;
+            let pool_id = u256_to_num(pool_id).ok_or(PoolError::GeneralError(
+                "failed to parse pool_id".to_string(),
+            ))?;
+            Ok(self.wrap(pool_id))
+        }
+
+        #[span(name = "pinned_pool.pool_index")]
+        pub async fn pool_index(&self) -> Result<AtBlock<U256>, PoolError> {
+            let pool_index = self.query_value("pool_index").await?;
+            Ok(self.wrap(pool_index))
+        }
+
+        /// Reads the pool index and its root at this block.
+        #[span(name = "pinned_pool.root")]
+        pub async fn root(&self) -> Result<AtBlock<(U256, Num<Fr>)>, PoolError> {
+            let pool_index = self.query_value("pool_index").await?;
+            let root = self.pool.root_u256(pool_index, Some(self.pinned())).await?;
+
+            let root = u256_to_num(root)
+                .ok_or(PoolError::GeneralError("failed to parse root".to_string()))?;
+
+            tracing::debug!(
+                "got root from contract {} at block {}",
+                root,
+                self.block_number
+            );
+
+            Ok(self.wrap((pool_index, root)))
+        }
+
+        #[span(name = "pinned_pool.all_messages_hash")]
+        pub async fn all_messages_hash(&self) -> Result<AtBlock<H256>, PoolError> {
+            self.query("all_messages_hash").await
+        }
+
+        #[span(name = "pinned_pool.denominator")]
+        pub async fn denominator(&self) -> Result<AtBlock<U256>, PoolError> {
+            self.query("denominator").await
+        }
+
+        #[span(name = "pinned_pool.energy_denominator")]
+        pub async fn energy_denominator(&self) -> Result<AtBlock<U256>, PoolError> {
+            self.query("energy_denominator").await
+        }
+
+        #[span(name = "pinned_pool.native_denominator")]
+        pub async fn native_denominator(&self) -> Result<AtBlock<U256>, PoolError> {
+            self.query("native_denominator").await
+        }
+
+        #[span(name = "pinned_pool.operator_manager")]
+        pub async fn operator_manager(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("operatorManager").await
+        }
+
+        #[span(name = "pinned_pool.token")]
+        pub async fn token(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("token").await
+        }
+
+        #[span(name = "pinned_pool.transfer_verifier")]
+        pub async fn transfer_verifier(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("transfer_verifier").await
+        }
+
+        #[span(name = "pinned_pool.tree_verifier")]
+        pub async fn tree_verifier(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("tree_verifier").await
+        }
+
+        #[span(name = "pinned_pool.voucher_token")]
+        pub async fn voucher_token(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("voucher_token").await
+        }
+
+        #[span(name = "pinned_pool.direct_deposit_queue")]
+        pub async fn direct_deposit_queue(&self) -> Result<AtBlock<H160>, PoolError> {
+            self.query("direct_deposit_queue").await
+        }
+
+        /// Fetches all pool parameters concurrently at this block.
+        #[span(name = "pinned_pool.info")]
+        pub async fn info(&self) -> Result<AtBlock<PoolInfo>, PoolError> {
+            let info = self.pool.info_at(Some(self.block)).await?;
+            Ok(self.wrap(info))
+        }
+    }
+
+    async fn query<R: Detokenize>(&self, func: &str) -> Result<AtBlock<R>, PoolError> {
+        let value = self.query_value(func).await?;
+        Ok(self.wrap(value))
+    }
+
+    async fn query_value<R: Detokenize>(&self, func: &str) -> Result<R, PoolError> {
+        self.pool.query_value(func, Some(self.block)).await
+    }
+
+    fn pinned(&self) -> (BlockId, U64) {
+        (self.block, self.block_number)
+    }
+
+    fn wrap<V>(&self, value: V) -> AtBlock<V> {
+        AtBlock {
+            block_number: self.block_number,
+            value,
+        }
+    }
+}
@@ -1,28 +1,68 @@
-use ethabi::ethereum_types::U64;
+use ethabi::{ethereum_types::U64, Token};
 use libzeropool::fawkes_crypto::{engines::bn256::Fr, ff_uint::{Num, Uint, NumRepr}};
 use secp256k1::SecretKey;
-use std::{str::FromStr, time::Duration};
+use std::{future::Future, str::FromStr, time::Duration};
 use tokio::time::timeout;
 use web3::{
-    contract::{Contract, Options},
+    contract::{
+        tokens::{Detokenize, Tokenize},
+        Contract, Options,
+    },
     transports::Http,
     types::{
         BlockId, BlockNumber, Bytes, FilterBuilder, Log, LogWithMeta, Transaction, TransactionId,
         TransactionReceipt, H160, H256, U256,
     },
-    Error as Web3Error, Web3,
+    Error as Web3Error, Transport, Web3,
 };
 
-use crate::configuration::Web3Settings;
+use crate::{
+    configuration::Web3Settings,
+    metrics::{self, ErrorKind},
+    telemetry::span::traced,
+};
 
-use super::{error::PoolError, dd::DdContract};
+use super::{
+    cache::{CacheKey, Cacheable, PoolCache},
+    dd::DdContract,
+    error::PoolError,
+    events::{block_id_label, block_range, decode_logs, take_param, ContractEvent},
+    multicall::{Multicall, MULTICALL3_ADDRESS},
+    pinned::{AtBlock, PinnedPool},
+    token::TokenContract,
+};
 
 type MessageEvent = (U256, H256, Bytes);
 type Events = Vec<LogWithMeta<MessageEvent>>;
 
-pub struct Pool {
-    pub contract: Contract<Http>,
-    web3: Web3<Http>,
+/// Decoded pool `Message` event.
+#[derive(Debug, Clone)]
+pub struct PoolMessage {
+    pub index: U256,
+    pub hash: H256,
+    pub message: Bytes,
+}
+
+/// Snapshot of the pool contract parameters that are set at deployment or by governance.
+#[derive(Debug, Clone)]
+pub struct PoolInfo {
+    pub all_messages_hash: H256,
+    pub denominator: U256,
+    pub energy_denominator: U256,
+    pub native_denominator: U256,
+    pub operator_manager: H160,
+    pub token: H160,
+    pub transfer_verifier: H160,
+    pub tree_verifier: H160,
+    pub voucher_token: H160,
+}
+
+pub struct Pool<T: Transport = Http> {
+    pub contract: Contract<T>,
+    web3: Web3<T>,
+    multicall: Multicall<T>,
+    cache: Option<PoolCache>,
+    endpoint: String,
 
     key: Option<SecretKey>,
     gas_limit: Option<U256>,
@@ -32,34 +72,61 @@ pub struct Pool {
 
 impl Pool {
     pub fn new(config: &Web3Settings) -> Result<Self, PoolError> {
-        let contract_address = H160::from_str(&config.pool_address).expect("bad pool address");
+        let http = web3::transports::Http::new(&config.provider_endpoint)?;
+        Self::with_transport(config, http)
+    }
+}
 
-        let http = web3::transports::Http::new(&config.provider_endpoint)
-            .expect("failed to init web3 provider");
-        let web3 = web3::Web3::new(http);
+impl<T: Transport> Pool<T> {
+    /// Creates a pool client on top of a custom transport, e.g. [`crate::transport::BatchingTransport`].
+    pub fn with_transport(config: &Web3Settings, transport: T) -> Result<Self, PoolError> {
+        let contract_address = H160::from_str(&config.pool_address).map_err(|err| {
+            PoolError::GeneralError(format!("bad pool address {}: {}", config.pool_address, err))
+        })?;
+
+        let web3 = web3::Web3::new(transport);
 
         let contract = Contract::from_json(
             web3.eth(),
             contract_address,
             include_bytes!("pool-abi.json"),
-        )
-        .expect("failed to read contract");
+        )?;
 
         let key = config
             .secret_key
             .as_ref()
-            .map(|sk| SecretKey::from_str(sk).unwrap());
+            .map(|sk| SecretKey::from_str(sk.expose()))
+            .transpose()
+            .map_err(|err| PoolError::GeneralError(format!("bad secret key: {}", err)))?;
+
+        let multicall_address = config
+            .multicall_address
+            .as_deref()
+            .unwrap_or(MULTICALL3_ADDRESS);
+        let multicall_address = H160::from_str(multicall_address).map_err(|err| {
+            PoolError::GeneralError(format!(
+                "bad multicall address {}: {}",
+                multicall_address, err
+            ))
+        })?;
+        let multicall = Multicall::new(
+            multicall_address,
+            web3.clone(),
+            Duration::from_secs(config.provider_timeout_sec),
+        )?;
 
         let short_signature = contract
             .abi()
-            .function("transact")
-            .unwrap()
+            .function("transact")?
             .short_signature()
             .to_vec();
 
         Ok(Self {
             contract,
             web3,
+            multicall,
+            cache: config.cache.clone().map(PoolCache::new),
+            endpoint: metrics::endpoint_label(&config.provider_endpoint),
             key,
             gas_limit: config.gas_limit.map(U256::from),
             transact_short_signature: short_signature,
@@ -67,182 +134,865 @@ impl Pool {
         })
     }
 
-    pub async fn nullifier_exists(&self, nullifier: Num<Fr>) -> Result<bool, PoolError> {
-        let nullifier = num_to_u256(nullifier);
-        let exists: U256 = timeout(
-            self.timeout,
-            self.contract
-                .query("nullifiers", (nullifier,), None, Options::default(), None),
-        )
-        .await??;
-        Ok(!exists.is_zero())
-    }
-
-    pub async fn root_by_index(&self, index: Num<Fr>) -> Result<Num<Fr>, PoolError> {
-        let contract = &self.contract;
-        let index = num_to_u256(index);
-        let result = contract.query("roots", (index,), None, Options::default(), None);
-        let root: U256 = timeout(self.timeout, result).await??;
-        let root = u256_to_num(root)
-            .ok_or(PoolError::GeneralError("failed to parse root".to_string()))?;
-        Ok(root)
+    /// The read-through cache, if enabled in [`Web3Settings::cache`].
+    pub fn cache(&self) -> Option<&PoolCache> {
+        self.cache.as_ref()
     }
 
-    pub async fn pool_id(&self) -> Result<Num<Fr>, PoolError> {
-        let result = self.contract.query("pool_id", (), None, Options::default(), None);
-        let pool_id = timeout(self.timeout, result).await??;
-        let pool_id = u256_to_num(pool_id)
-            .ok_or(PoolError::GeneralError("failed to parse pool_id".to_string()))?;
-        Ok(pool_id)
-    }
+    traced! {
+        fields(contract = ?self.contract.address(), endpoint = %self.endpoint);
+
+        /// Compares the last remembered block with the chain and drops cached data
+        /// that may have changed if that block was replaced. Returns `true` on reorg.
+        ///
+        /// Call it periodically, e.g. before each indexing iteration. The head it
+        /// records also tells the cache which roots and timestamps are final.
+        #[span(name = "pool.check_reorg")]
+        pub async fn check_reorg(&self) -> Result<bool, PoolError> {
+            let cache = match &self.cache {
+                Some(cache) => cache,
+                None => return Ok(false),
+            };
+
+            let mut reorg = false;
+            if let Some((number, hash)) = cache.head() {
+                let block = self
+                    .rpc(
+                        "eth_getBlockByNumber",
+                        self.web3
+                            .eth()
+                            .block(BlockId::Number(BlockNumber::Number(number))),
+                    )
+                    .await?;
+                if block.and_then(|block| block.hash) != Some(hash) {
+                    tracing::warn!("block {} was replaced, invalidating pool cache", number);
+                    cache.invalidate();
+                    reorg = true;
+                }
+            }
+
+            let latest = self
+                .rpc(
+                    "eth_getBlockByNumber",
+                    self.web3.eth().block(BlockId::Number(BlockNumber::Latest)),
+                )
+                .await?;
+            if let Some((Some(number), Some(hash))) = latest.map(|block| (block.number, block.hash))
+            {
+                cache.set_head(number, hash);
+            }
+            Ok(reorg)
+        }
 
-    pub async fn get_transaction(&self, tx_hash: H256) -> Result<Option<Transaction>, PoolError> {
-        let tx = timeout(
-            self.timeout,
-            self.web3.eth().transaction(TransactionId::Hash(tx_hash)),
-        )
-        .await??;
-        Ok(tx)
-    }
+        #[span(name = "pool.nullifier_exists")]
+        pub async fn nullifier_exists(&self, nullifier: Num<Fr>) -> Result<bool, PoolError> {
+            let nullifier = num_to_u256(nullifier);
+            let exists: U256 = self.query_with("nullifiers", (nullifier,), None).await?;
+            Ok(!exists.is_zero())
+        }
 
-    pub async fn block_timestamp(&self, block_number: U64) -> Result<Option<U256>, PoolError> {
-        let block = timeout(
-            self.timeout,
-            self
-            .web3
-            .eth()
-            .block(BlockId::Number(BlockNumber::Number(block_number))))
-            .await??;
-        match block {
-            Some(block) => Ok(Some(block.timestamp)),
-            None => Ok(None)
+        /// Checks many nullifiers with Multicall3, preserving the order of `nullifiers`.
+        #[span(name = "pool.nullifiers_exist", skip(nullifiers))]
+        pub async fn nullifiers_exist(
+            &self,
+            nullifiers: &[Num<Fr>],
+        ) -> Result<Vec<Result<bool, PoolError>>, PoolError> {
+            let args = nullifiers
+                .iter()
+                .map(|n| num_to_u256(*n))
+                .collect::<Vec<_>>();
+            let results = self.multicall_u256("nullifiers", &args, None).await?;
+            Ok(results
+                .into_iter()
+                .map(|exists| exists.map(|exists| !exists.is_zero()))
+                .collect())
         }
-    }
 
-    pub async fn block_number(&self) -> Result<U64, PoolError> {
-        let block_number = timeout(self.timeout, self.web3.eth().block_number()).await??;
-        Ok(block_number)
-    }
+        /// Reads many `roots` entries with Multicall3, preserving the order of `indices`.
+        #[span(name = "pool.roots_by_indices", skip(indices))]
+        pub async fn roots_by_indices(
+            &self,
+            indices: &[U256],
+        ) -> Result<Vec<Result<Num<Fr>, PoolError>>, PoolError> {
+            let results = self.multicall_u256("roots", indices, None).await?;
+            Ok(results
+                .into_iter()
+                .map(|root| {
+                    root.and_then(|root| {
+                        u256_to_num(root)
+                            .ok_or(PoolError::GeneralError("failed to parse root".to_string()))
+                    })
+                })
+                .collect())
+        }
 
-    pub async fn dd_contract(&self) -> Result<DdContract, PoolError> {
-        let result = self.contract.query("direct_deposit_queue", (), None, Options::default(), None);
-        let dd_contract_address = timeout(self.timeout, result).await??;
-        DdContract::new(dd_contract_address, self.web3.clone(), self.timeout)
-    }
+        #[span(name = "pool.root_by_index")]
+        pub async fn root_by_index(&self, index: Num<Fr>) -> Result<Num<Fr>, PoolError> {
+            let root = self.root_u256(num_to_u256(index), None).await?;
+            let root = u256_to_num(root)
+                .ok_or(PoolError::GeneralError("failed to parse root".to_string()))?;
+            Ok(root)
+        }
 
-    pub async fn chain_id(&self) -> Result<U256, PoolError> {
-        let chain_id = timeout(self.timeout, self.web3.eth().chain_id()).await??;
-        Ok(chain_id)
-    }
+        #[span(name = "pool.pool_id")]
+        pub async fn pool_id(&self) -> Result<Num<Fr>, PoolError> {
+            if let Some(pool_id) = self.cached(CacheKey::PoolId) {
+                return Ok(pool_id);
+            }
+            let pool_id = self.query_value("pool_id", None).await?;
+            let pool_id = u256_to_num(pool_id).ok_or(PoolError::GeneralError(
+                "failed to parse pool_id".to_string(),
+            ))?;
+            self.cache_value(CacheKey::PoolId, pool_id);
+            Ok(pool_id)
+        }
 
-    // TODO: refactor methods below
+        #[span(name = "pool.pool_index")]
+        pub async fn pool_index(&self) -> Result<U256, PoolError> {
+            if let Some(pool_index) = self.cached(CacheKey::PoolIndex) {
+                return Ok(pool_index);
+            }
+            let pool_index = self.query_value("pool_index", None).await?;
+            if let Some(cache) = &self.cache {
+                cache.observe_pool_index(pool_index);
+            }
+            self.report_pool_index(pool_index);
+            Ok(pool_index)
+        }
 
-    pub async fn get_transaction_receipt(
-        &self,
-        tx_hash: H256,
-    ) -> Result<Option<TransactionReceipt>, web3::Error> {
-        self.web3.eth().transaction_receipt(tx_hash).await
-    }
+        #[span(name = "pool.get_transaction")]
+        pub async fn get_transaction(
+            &self,
+            tx_hash: H256,
+        ) -> Result<Option<Transaction>, PoolError> {
+            let tx = self
+                .rpc(
+                    "eth_getTransactionByHash",
+                    self.web3.eth().transaction(TransactionId::Hash(tx_hash)),
+                )
+                .await?;
+            Ok(tx)
+        }
 
-    pub async fn root(&self) -> Result<(U256, Num<Fr>), PoolError> {
-        let contract = &self.contract;
-        let result = contract.query("pool_index", (), None, Options::default(), None);
-        let pool_index = timeout(self.timeout, result).await??;
+        #[span(name = "pool.block_timestamp", skip(block_number), fields(block = %block_number))]
+        pub async fn block_timestamp(&self, block_number: U64) -> Result<Option<U256>, PoolError> {
+            if let Some(timestamp) = self.cached(CacheKey::BlockTimestamp(block_number)) {
+                return Ok(Some(timestamp));
+            }
+            let block = self
+                .rpc(
+                    "eth_getBlockByNumber",
+                    self.web3
+                        .eth()
+                        .block(BlockId::Number(BlockNumber::Number(block_number))),
+                )
+                .await?;
+            match block {
+                Some(block) => {
+                    self.cache_value(CacheKey::BlockTimestamp(block_number), block.timestamp);
+                    Ok(Some(block.timestamp))
+                }
+                None => Ok(None),
+            }
+        }
 
-        let result = contract.query("roots", (pool_index,), None, Options::default(), None);
+        #[span(name = "pool.block_number")]
+        pub async fn block_number(&self) -> Result<U64, PoolError> {
+            let block_number = self
+                .rpc("eth_blockNumber", self.web3.eth().block_number())
+                .await?;
+            Ok(block_number)
+        }
 
-        let root: U256 = timeout(self.timeout, result).await??;
+        #[span(name = "pool.dd_contract")]
+        pub async fn dd_contract(&self) -> Result<DdContract<T>, PoolError> {
+            let dd_contract_address = match self.cached(CacheKey::DdQueueAddress) {
+                Some(address) => address,
+                None => {
+                    let address = self.query_value("direct_deposit_queue", None).await?;
+                    self.cache_value(CacheKey::DdQueueAddress, address);
+                    address
+                }
+            };
+            let dd_contract =
+                DdContract::new(dd_contract_address, self.web3.clone(), self.timeout)?
+                    .with_endpoint(self.endpoint.clone());
+            Ok(match self.key {
+                Some(key) => dd_contract.with_key(key, self.gas_limit),
+                None => dd_contract,
+            })
+        }
 
-        let root = Num::from_str(&root.to_string())
-            .map_err(|_| PoolError::GeneralError("failed to parse root".to_string()))?;
+        #[span(name = "pool.token_contract")]
+        pub async fn token_contract(&self) -> Result<TokenContract<T>, PoolError> {
+            let token_address = self.token().await?;
+            let token_contract =
+                TokenContract::new(token_address, self.web3.clone(), self.timeout)?
+                    .with_endpoint(self.endpoint.clone());
+            Ok(match self.key {
+                Some(key) => token_contract.with_key(key),
+                None => token_contract,
+            })
+        }
 
-        tracing::debug!("got root from contract {}", root);
+        #[span(name = "pool.chain_id")]
+        pub async fn chain_id(&self) -> Result<U256, PoolError> {
+            if let Some(chain_id) = self.cached(CacheKey::ChainId) {
+                return Ok(chain_id);
+            }
+            let chain_id = self.rpc("eth_chainId", self.web3.eth().chain_id()).await?;
+            self.cache_value(CacheKey::ChainId, chain_id);
+            Ok(chain_id)
+        }
 
-        Ok((pool_index, root))
-    }
+        #[span(name = "pool.all_messages_hash")]
+        pub async fn all_messages_hash(&self) -> Result<H256, PoolError> {
+            self.query_value("all_messages_hash", None).await
+        }
 
-    pub async fn get_events(
-        &self,
-        from_block: Option<BlockNumber>,
-        to_block: Option<BlockNumber>,
-        block_hash: Option<H256>,
-    ) -> Result<Events, PoolError> {
-        let result = self
-            .contract
-            .events("Message", from_block, to_block, block_hash, (), (), ());
+        #[span(name = "pool.denominator")]
+        pub async fn denominator(&self) -> Result<U256, PoolError> {
+            self.query_value("denominator", None).await
+        }
 
-        let events: Events = timeout(self.timeout, result).await??;
+        #[span(name = "pool.energy_denominator")]
+        pub async fn energy_denominator(&self) -> Result<U256, PoolError> {
+            self.query_value("energy_denominator", None).await
+        }
 
-        Ok(events)
-    }
+        #[span(name = "pool.native_denominator")]
+        pub async fn native_denominator(&self) -> Result<U256, PoolError> {
+            self.query_value("native_denominator", None).await
+        }
 
-    pub async fn get_logs(&self) -> Result<Vec<Log>, PoolError> {
-        let res = self.contract.abi().event("Message").and_then(|ev| {
-            let filter = ev.filter(ethabi::RawTopicFilter {
-                topic0: ethabi::Topic::Any,
-                topic1: ethabi::Topic::Any,
-                topic2: ethabi::Topic::Any,
-            })?;
-            Ok((ev.clone(), filter))
-        });
-        let (_ev, filter) = match res {
-            Ok(x) => x,
-            Err(_e) => return Err(PoolError::GeneralError("WTF".to_string())),
-        };
+        #[span(name = "pool.operator_manager")]
+        pub async fn operator_manager(&self) -> Result<H160, PoolError> {
+            self.query_value("operatorManager", None).await
+        }
+
+        #[span(name = "pool.token")]
+        pub async fn token(&self) -> Result<H160, PoolError> {
+            self.query_value("token", None).await
+        }
+
+        #[span(name = "pool.transfer_verifier")]
+        pub async fn transfer_verifier(&self) -> Result<H160, PoolError> {
+            self.query_value("transfer_verifier", None).await
+        }
+
+        #[span(name = "pool.tree_verifier")]
+        pub async fn tree_verifier(&self) -> Result<H160, PoolError> {
+            self.query_value("tree_verifier", None).await
+        }
+
+        #[span(name = "pool.voucher_token")]
+        pub async fn voucher_token(&self) -> Result<H160, PoolError> {
+            self.query_value("voucher_token", None).await
+        }
 
-        let address = self.contract.address();
-        tracing::info!("filter {:#?}", filter);
-        tracing::info!("address {:#?}", address);
-
-        let logs = self
-            .web3
-            .eth()
-            .logs(
-                FilterBuilder::default()
-                    .address(vec![self.contract.address()])
-                    .topic_filter(filter)
-                    .from_block(Some(BlockNumber::Earliest))
-                    .to_block(Some(BlockNumber::Latest))
-                    .block_hash(None)
-                    .build(),
+        /// Fetches all pool parameters concurrently.
+        #[span(name = "pool.info")]
+        pub async fn info(&self) -> Result<PoolInfo, PoolError> {
+            self.info_at(None).await
+        }
+
+        // TODO: refactor methods below
+
+        #[span(name = "pool.get_transaction_receipt")]
+        pub async fn get_transaction_receipt(
+            &self,
+            tx_hash: H256,
+        ) -> Result<Option<TransactionReceipt>, web3::Error> {
+            self.observe(
+                "eth_getTransactionReceipt",
+                self.web3.eth().transaction_receipt(tx_hash),
             )
             .await
-            .unwrap();
+        }
+
+        /// Reads the current pool index and its root at the same block.
+        #[span(name = "pool.root")]
+        pub async fn root(&self) -> Result<AtBlock<(U256, Num<Fr>)>, PoolError> {
+            let root = self
+                .at(BlockId::Number(BlockNumber::Latest))
+                .await?
+                .root()
+                .await?;
+            if let Some(cache) = &self.cache {
+                cache.observe_pool_index(root.value.0);
+            }
+            self.report_pool_index(root.value.0);
+            Ok(root)
+        }
+
+        /// Pins subsequent reads to `block`, resolving tags such as `latest` to a
+        /// concrete block number first.
+        #[span(name = "pool.at", skip(block), fields(block = %block_id_label(block)))]
+        pub async fn at(&self, block: BlockId) -> Result<PinnedPool<'_, T>, PoolError> {
+            let (block, block_number) = match block {
+                BlockId::Number(BlockNumber::Number(number)) => (block, number),
+                BlockId::Number(BlockNumber::Latest) => {
+                    let number = self.block_number().await?;
+                    (BlockId::Number(BlockNumber::Number(number)), number)
+                }
+                block => {
+                    let method = match block {
+                        BlockId::Hash(_) => "eth_getBlockByHash",
+                        BlockId::Number(_) => "eth_getBlockByNumber",
+                    };
+                    let header = self
+                        .rpc(method, self.web3.eth().block(block))
+                        .await?
+                        .ok_or_else(|| {
+                            PoolError::GeneralError(format!("block {:?} not found", block))
+                        })?;
+                    let number = header
+                        .number
+                        .ok_or_else(|| PoolError::GeneralError("block is pending".to_string()))?;
+                    match block {
+                        // keep the hash so that reads fail instead of silently following a reorg
+                        BlockId::Hash(_) => (block, number),
+                        _ => (BlockId::Number(BlockNumber::Number(number)), number),
+                    }
+                }
+            };
+            Ok(PinnedPool::new(self, block, block_number))
+        }
+
+        #[span(
+            name = "pool.get_events",
+            skip(from_block, to_block),
+            fields(block = %block_range(from_block, to_block)),
+        )]
+        pub async fn get_events(
+            &self,
+            from_block: Option<BlockNumber>,
+            to_block: Option<BlockNumber>,
+            block_hash: Option<H256>,
+        ) -> Result<Events, PoolError> {
+            let result =
+                self.contract
+                    .events("Message", from_block, to_block, block_hash, (), (), ());
+
+            let events: Events = self.rpc("eth_getLogs", result).await?;
+
+            Ok(events)
+        }
+
+        /// Returns `Message` events along with the transactions that emitted them.
+        #[span(
+            name = "pool.message_events",
+            skip(from_block, to_block),
+            fields(block = %block_range(from_block, to_block)),
+        )]
+        pub async fn message_events(
+            &self,
+            from_block: Option<BlockNumber>,
+            to_block: Option<BlockNumber>,
+        ) -> Result<Vec<ContractEvent<PoolMessage>>, PoolError> {
+            let event = self.contract.abi().event("Message")?;
+            let filter = FilterBuilder::default()
+                .address(vec![self.contract.address()])
+                .topics(Some(vec![event.signature()]), None, None, None)
+                .from_block(from_block)
+                .to_block(to_block)
+                .build();
+
+            let logs = self
+                .rpc("eth_getLogs", self.web3.eth().logs(filter))
+                .await?;
+            decode_logs(event, logs, |mut params| {
+                let index = take_param(&mut params, "index")?;
+                let hash = take_param(&mut params, "hash")?;
+                let message = take_param(&mut params, "message")?;
+                match (index, hash, message) {
+                    (Token::Uint(index), Token::FixedBytes(hash), Token::Bytes(message))
+                        if hash.len() == 32 =>
+                    {
+                        Ok(PoolMessage {
+                            index,
+                            hash: H256::from_slice(&hash),
+                            message: Bytes(message),
+                        })
+                    }
+                    _ => Err(PoolError::GeneralError(
+                        "failed to parse Message event".to_string(),
+                    )),
+                }
+            })
+        }
+
+        #[span(name = "pool.get_logs")]
+        pub async fn get_logs(&self) -> Result<Vec<Log>, PoolError> {
+            let res = self.contract.abi().event("Message").and_then(|ev| {
+                let filter = ev.filter(ethabi::RawTopicFilter {
+                    topic0: ethabi::Topic::Any,
+                    topic1: ethabi::Topic::Any,
+                    topic2: ethabi::Topic::Any,
+                })?;
+                Ok((ev.clone(), filter))
+            });
+            let (_ev, filter) = match res {
+                Ok(x) => x,
+                Err(_e) => return Err(PoolError::GeneralError("WTF".to_string())),
+            };
+
+            let address = self.contract.address();
+            tracing::info!("filter {:#?}", filter);
+            tracing::info!("address {:#?}", address);
+
+            let logs = self
+                .observe(
+                    "eth_getLogs",
+                    self.web3.eth().logs(
+                        FilterBuilder::default()
+                            .address(vec![self.contract.address()])
+                            .topic_filter(filter)
+                            .from_block(Some(BlockNumber::Earliest))
+                            .to_block(Some(BlockNumber::Latest))
+                            .block_hash(None)
+                            .build(),
+                    ),
+                )
+                .await
+                .unwrap();
+
+            Ok(logs)
+        }
+
+        #[span(name = "pool.send_tx", skip(tx_data))]
+        pub async fn send_tx(&self, tx_data: Vec<u8>) -> Result<H256, String> {
+            let fn_data: Vec<u8> = vec![self.transact_short_signature.clone(), tx_data].concat();
+
+            let gas_price = self.gas_price().await.map_err(|e| e.to_string())?;
+
+            let options = Options {
+                gas: Some(self.gas_limit.unwrap()),
+                gas_price: Some(gas_price),
+                ..Default::default()
+            };
+
+            let tx_hash = self
+                .observe_function(
+                    "eth_sendRawTransaction",
+                    "transact",
+                    self.contract
+                        .signed_call_raw(fn_data, options, &self.key.unwrap()),
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+
+            Ok(tx_hash)
+        }
+    }
 
-        Ok(logs)
+    async fn gas_price(&self) -> Result<U256, Web3Error> {
+        self.observe("eth_gasPrice", self.web3.eth().gas_price())
+            .await
     }
 
-    pub async fn send_tx(&self, tx_data: Vec<u8>) -> Result<H256, String> {
-        let fn_data: Vec<u8> = vec![self.transact_short_signature.clone(), tx_data].concat();
+    pub(super) async fn info_at(&self, block: Option<BlockId>) -> Result<PoolInfo, PoolError> {
+        let (
+            all_messages_hash,
+            denominator,
+            energy_denominator,
+            native_denominator,
+            operator_manager,
+            token,
+            transfer_verifier,
+            tree_verifier,
+            voucher_token,
+        ) = tokio::try_join!(
+            self.query_value("all_messages_hash", block),
+            self.query_value("denominator", block),
+            self.query_value("energy_denominator", block),
+            self.query_value("native_denominator", block),
+            self.query_value("operatorManager", block),
+            self.query_value("token", block),
+            self.query_value("transfer_verifier", block),
+            self.query_value("tree_verifier", block),
+            self.query_value("voucher_token", block),
+        )?;
+
+        Ok(PoolInfo {
+            all_messages_hash,
+            denominator,
+            energy_denominator,
+            native_denominator,
+            operator_manager,
+            token,
+            transfer_verifier,
+            tree_verifier,
+            voucher_token,
+        })
+    }
 
-        let gas_price = self.gas_price().await.map_err(|e| e.to_string())?;
+    pub(super) async fn multicall_u256(
+        &self,
+        func: &str,
+        args: &[U256],
+        block: Option<BlockId>,
+    ) -> Result<Vec<Result<U256, PoolError>>, PoolError> {
+        let function = self.contract.abi().function(func)?;
+        let calls = args
+            .iter()
+            .map(|arg| {
+                let data = function.encode_input(&[Token::Uint(*arg)])?;
+                Ok((self.contract.address(), data))
+            })
+            .collect::<Result<Vec<_>, PoolError>>()?;
+
+        let results = self
+            .observe_function(
+                "eth_call",
+                &format!("aggregate3({})", func),
+                self.multicall.aggregate(calls, block),
+            )
+            .await?;
+        Ok(results
+            .into_iter()
+            .map(|data| {
+                let output = function.decode_output(&data?)?;
+                match output.as_slice() {
+                    [Token::Uint(value)] => Ok(*value),
+                    _ => Err(PoolError::GeneralError(format!(
+                        "unexpected {} output",
+                        func
+                    ))),
+                }
+            })
+            .collect())
+    }
 
-        let options = Options {
-            gas: Some(self.gas_limit.unwrap()),
-            gas_price: Some(gas_price),
-            ..Default::default()
-        };
+    /// Reads `roots(index)` at the latest block or at a pinned block and its
+    /// number. Pinned reads skip cached roots, which may not exist yet at that block.
+    pub(super) async fn root_u256(
+        &self,
+        index: U256,
+        pinned: Option<(BlockId, U64)>,
+    ) -> Result<U256, PoolError> {
+        if pinned.is_none() {
+            if let Some(root) = self.cached(CacheKey::Root(index)) {
+                return Ok(root);
+            }
+        }
+        let block = pinned.map(|(block, _)| block);
+        let root: U256 = self.query_with("roots", (index,), block).await?;
+        // roots above the current pool index are not written yet
+        if !root.is_zero() {
+            if let Some(cache) = &self.cache {
+                cache.insert_at(
+                    CacheKey::Root(index),
+                    root,
+                    pinned.map(|(_, number)| number),
+                );
+            }
+        }
+        Ok(root)
+    }
+
+    fn cached<V: Cacheable>(&self, key: CacheKey) -> Option<V> {
+        self.cache.as_ref().and_then(|cache| cache.get(key))
+    }
+
+    fn cache_value<V: Cacheable>(&self, key: CacheKey, value: V) {
+        if let Some(cache) = &self.cache {
+            cache.insert(key, value);
+        }
+    }
+
+    pub(super) async fn query_value<R: Detokenize>(
+        &self,
+        func: &str,
+        block: Option<BlockId>,
+    ) -> Result<R, PoolError> {
+        self.query_with(func, (), block).await
+    }
 
-        let tx_hash = self
+    pub(super) async fn query_with<R: Detokenize, P: Tokenize>(
+        &self,
+        func: &str,
+        params: P,
+        block: Option<BlockId>,
+    ) -> Result<R, PoolError> {
+        let result = self
             .contract
-            .signed_call_raw(fn_data, options, &self.key.unwrap())
+            .query(func, params, None, Options::default(), block);
+        self.observe_function("eth_call", func, async {
+            Ok(timeout(self.timeout, result).await??)
+        })
+        .await
+    }
+
+    /// Runs an RPC call with the provider timeout, reporting it to [`metrics`].
+    async fn rpc<R, E, F>(&self, method: &str, call: F) -> Result<R, PoolError>
+    where
+        F: Future<Output = Result<R, E>>,
+        PoolError: From<E>,
+    {
+        self.observe(method, async { Ok(timeout(self.timeout, call).await??) })
             .await
-            .map_err(|e| e.to_string())?;
+    }
 
-        Ok(tx_hash)
+    async fn observe<R, E, F>(&self, method: &str, call: F) -> Result<R, E>
+    where
+        E: ErrorKind,
+        F: Future<Output = Result<R, E>>,
+    {
+        self.observe_function(method, "", call).await
     }
 
-    async fn gas_price(&self) -> Result<U256, Web3Error> {
-        self.web3.eth().gas_price().await
+    /// Reports a `method` call of the contract `function`, see [`metrics::Metrics::observe_function`].
+    async fn observe_function<R, E, F>(&self, method: &str, function: &str, call: F) -> Result<R, E>
+    where
+        E: ErrorKind,
+        F: Future<Output = Result<R, E>>,
+    {
+        metrics::global()
+            .observe_function(metrics::POOL_CLIENT, method, function, &self.endpoint, call)
+            .await
+    }
+
+    /// RPC endpoint label reported in spans and metrics.
+    pub(super) fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn report_pool_index(&self, pool_index: U256) {
+        metrics::global().set_pool_index(&format!("{:?}", self.contract.address()), pool_index);
     }
 }
 
-fn u256_to_num(n: U256) -> Option<Num<Fr>> {
+pub(super) fn u256_to_num(n: U256) -> Option<Num<Fr>> {
     let mut buf = [0; 32];
     n.to_little_endian(&mut buf);
     Num::from_uint(NumRepr(Uint::from_little_endian(&buf)))
 }
 
-fn num_to_u256(n: Num<Fr>) -> U256 {
+pub(super) fn num_to_u256(n: Num<Fr>) -> U256 {
     U256::from_little_endian(&n.to_uint().0.to_little_endian())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use ethabi::Token;
+    use web3::types::{BlockId, BlockNumber, H160, H256, U256, U64};
+
+    use crate::configuration::{CacheSettings, Secret, Web3Settings};
+
+    use super::{
+        super::{
+            cache::CacheKey,
+            error::PoolError,
+            mock::{pool, settings, MockTransport},
+        },
+        num_to_u256, u256_to_num, Pool,
+    };
+
+    #[tokio::test]
+    async fn info_reads_all_parameters() {
+        let abi = pool(MockTransport::new(|_, _| None)).contract.abi().clone();
+        let transport = MockTransport::calls(move |call| {
+            assert_eq!(call.block, "latest");
+            let value = match call.function(&abi)?.0 {
+                "all_messages_hash" => Token::FixedBytes(vec![0xaa; 32]),
+                "denominator" => Token::Uint(U256::from(1_000_000_000u64)),
+                "energy_denominator" => Token::Uint(U256::from(2)),
+                "native_denominator" => Token::Uint(U256::from(3)),
+                "operatorManager" => Token::Address(H160::repeat_byte(0x11)),
+                "token" => Token::Address(H160::repeat_byte(0x12)),
+                "transfer_verifier" => Token::Address(H160::repeat_byte(0x13)),
+                "tree_verifier" => Token::Address(H160::repeat_byte(0x14)),
+                "voucher_token" => Token::Address(H160::repeat_byte(0x15)),
+                _ => return None,
+            };
+            Some(vec![value])
+        });
+        let pool = pool(transport.clone());
+
+        let info = pool.info().await.unwrap();
+        assert_eq!(info.all_messages_hash, H256::repeat_byte(0xaa));
+        assert_eq!(info.denominator, U256::from(1_000_000_000u64));
+        assert_eq!(info.energy_denominator, U256::from(2));
+        assert_eq!(info.native_denominator, U256::from(3));
+        assert_eq!(info.operator_manager, H160::repeat_byte(0x11));
+        assert_eq!(info.token, H160::repeat_byte(0x12));
+        assert_eq!(info.transfer_verifier, H160::repeat_byte(0x13));
+        assert_eq!(info.tree_verifier, H160::repeat_byte(0x14));
+        assert_eq!(info.voucher_token, H160::repeat_byte(0x15));
+        assert_eq!(transport.methods().len(), 9);
+
+        assert_eq!(pool.token().await.unwrap(), H160::repeat_byte(0x12));
+        assert_eq!(
+            pool.operator_manager().await.unwrap(),
+            H160::repeat_byte(0x11)
+        );
+        assert_eq!(pool.denominator().await.unwrap(), info.denominator);
+    }
+
+    #[test]
+    fn bad_settings_are_errors() {
+        let settings = settings();
+        let with = |settings: Web3Settings| {
+            Pool::with_transport(&settings, MockTransport::new(|_, _| None)).err()
+        };
+
+        assert!(with(settings.clone()).is_none());
+        assert!(with(Web3Settings {
+            pool_address: "0x01".to_string(),
+            ..settings.clone()
+        })
+        .is_some());
+        assert!(with(Web3Settings {
+            multicall_address: Some("multicall".to_string()),
+            ..settings.clone()
+        })
+        .is_some());
+        assert!(with(Web3Settings {
+            secret_key: Some(Secret::new("not a key".to_string())),
+            ..settings
+        })
+        .is_some());
+    }
+
+    #[tokio::test]
+    async fn pinned_reads_skip_cached_roots() {
+        let abi = pool(MockTransport::new(|_, _| None)).contract.abi().clone();
+        let calls = MockTransport::calls(move |call| match call.function(&abi)? {
+            ("pool_index", _) => Some(vec![Token::Uint(U256::from(128))]),
+            // the root of 128 is written at block 16
+            ("roots", _) if call.block == "0xf" => Some(vec![Token::Uint(U256::zero())]),
+            ("roots", _) => Some(vec![Token::Uint(U256::from(42))]),
+            _ => None,
+        });
+        let transport = MockTransport::new(move |method, params| match method {
+            "eth_blockNumber" => Some(serde_json::json!("0x14")),
+            _ => calls.answer(method, params),
+        });
+        let settings = Web3Settings {
+            cache: Some(CacheSettings::default()),
+            ..settings()
+        };
+        let pool = Pool::with_transport(&settings, transport).unwrap();
+
+        let root = pool.root().await.unwrap();
+        assert_eq!(root.block_number, U64::from(20));
+        assert_eq!(root.value.0, U256::from(128));
+        let cache = pool.cache().unwrap();
+        assert_eq!(
+            cache.get::<U256>(CacheKey::PoolIndex),
+            Some(U256::from(128))
+        );
+        assert_eq!(
+            cache.get::<U256>(CacheKey::Root(U256::from(128))),
+            Some(U256::from(42))
+        );
+
+        let pinned = pool
+            .at(BlockId::Number(BlockNumber::Number(U64::from(15))))
+            .await
+            .unwrap();
+        let root = pinned
+            .root_by_index(u256_to_num(U256::from(128)).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(root.block_number, U64::from(15));
+        assert_eq!(num_to_u256(root.value), U256::zero());
+    }
+
+    #[tokio::test]
+    async fn nullifiers_and_roots_are_multicalled() {
+        let abi = pool(MockTransport::new(|_, _| None)).contract.abi().clone();
+        let transport = MockTransport::multicall(move |target, call| {
+            assert_eq!(target, H160::repeat_byte(0x01));
+            let arg = match call.function(&abi)? {
+                (_, args) if args.is_empty() => return None,
+                (function, mut args) => (function, args.remove(0).into_uint()?),
+            };
+            match arg {
+                // reverted
+                ("nullifiers", n) if n == U256::from(3) => None,
+                ("nullifiers", n) => Some(vec![Token::Uint(n % 2)]),
+                // not a uint256
+                ("roots", index) if index == U256::from(6) => Some(vec![]),
+                ("roots", index) => Some(vec![Token::Uint(index * 10)]),
+                _ => None,
+            }
+        });
+        let pool = pool(transport.clone());
+
+        let nullifiers = [1u64, 2, 3]
+            .map(|n| u256_to_num(U256::from(n)).unwrap())
+            .to_vec();
+        let exist = pool.nullifiers_exist(&nullifiers).await.unwrap();
+        assert_eq!(exist.len(), 3);
+        assert!(exist[0].as_ref().unwrap());
+        assert!(!exist[1].as_ref().unwrap());
+        assert!(matches!(exist[2], Err(PoolError::CallReverted(_))));
+
+        let indices = [5u64, 4, 6].map(U256::from).to_vec();
+        let roots = pool.roots_by_indices(&indices).await.unwrap();
+        assert_eq!(num_to_u256(*roots[0].as_ref().unwrap()), U256::from(50));
+        assert_eq!(num_to_u256(*roots[1].as_ref().unwrap()), U256::from(40));
+        assert!(roots[2].is_err());
+        assert_eq!(transport.methods(), vec!["eth_call", "eth_call"]);
+
+        let err = pool
+            .multicall_u256("pool_index", &indices, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PoolError::ContractException(_)), "{:?}", err);
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_record_endpoint_and_outcome() {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let abi = pool(MockTransport::new(|_, _| None)).contract.abi().clone();
+        let pool = pool(MockTransport::calls(move |call| {
+            match call.function(&abi)?.0 {
+                "direct_deposit_queue" => Some(vec![Token::Address(H160::repeat_byte(0x02))]),
+                _ => None,
+            }
+        }));
+        pool.chain_id().await.unwrap_err();
+        pool.dd_contract().await.unwrap().fee().await.unwrap_err();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let errors = output.lines().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2, "{}", output);
+        assert!(errors[0].contains(&format!(
+            "pool.chain_id{{contract={:?} endpoint=http://localhost:8545 outcome=\"error\"}}",
+            H160::repeat_byte(0x01)
+        )));
+        assert!(errors[1].contains(&format!(
+            "dd.fee{{contract={:?} endpoint=http://localhost:8545 outcome=\"error\"}}",
+            H160::repeat_byte(0x02)
+        )));
+        assert!(errors.iter().all(|error| error.contains("error=")));
+    }
+}
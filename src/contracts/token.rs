@@ -0,0 +1,425 @@
+use std::{future::Future, time::Duration};
+
+use ethabi::{ethereum_types::H160, Token};
+use secp256k1::SecretKey;
+use tokio::time::timeout;
+use tracing::field;
+use web3::{
+    contract::{Contract, Options},
+    signing::{keccak256, Key, SecretKeyRef},
+    transports::Http,
+    types::{H256, U256},
+    Transport, Web3,
+};
+
+use crate::{
+    amount::TokenAmount,
+    metrics::{self, TOKEN_CLIENT},
+    telemetry::span::traced,
+};
+
+use super::error::PoolError;
+
+const PERMIT_TYPE: &str =
+    "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)";
+const PERMIT_WITH_SALT_TYPE: &str = "PermitWithSalt(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline,bytes32 salt)";
+
+/// EIP-712 permit message accepted by the pool token.
+///
+/// zkBob deposits use the salted variant with the transaction nullifier as salt,
+/// which binds the signature to a single pool transaction.
+#[derive(Debug, Clone)]
+pub struct Permit {
+    pub owner: H160,
+    pub spender: H160,
+    pub value: TokenAmount,
+    pub nonce: U256,
+    pub deadline: U256,
+    pub salt: Option<H256>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermitSignature {
+    /// Recovery value, either 27 or 28 as taken by `permit(..., v, r, s)`.
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+impl Permit {
+    pub fn struct_hash(&self) -> H256 {
+        let mut tokens = vec![
+            Token::FixedBytes(keccak256(self.type_string().as_bytes()).to_vec()),
+            Token::Address(self.owner),
+            Token::Address(self.spender),
+            Token::Uint(self.value.0),
+            Token::Uint(self.nonce),
+            Token::Uint(self.deadline),
+        ];
+        if let Some(salt) = self.salt {
+            tokens.push(Token::FixedBytes(salt.as_bytes().to_vec()));
+        }
+        H256(keccak256(&ethabi::encode(&tokens)))
+    }
+
+    /// Returns the EIP-712 digest that has to be signed by the owner.
+    pub fn digest(&self, domain_separator: H256) -> H256 {
+        let mut message = Vec::with_capacity(66);
+        message.extend_from_slice(b"\x19\x01");
+        message.extend_from_slice(domain_separator.as_bytes());
+        message.extend_from_slice(self.struct_hash().as_bytes());
+        H256(keccak256(&message))
+    }
+
+    pub fn sign(
+        &self,
+        domain_separator: H256,
+        key: &SecretKey,
+    ) -> Result<PermitSignature, PoolError> {
+        let signature = SecretKeyRef::new(key)
+            .sign_message(self.digest(domain_separator).as_bytes())
+            .map_err(|e| PoolError::GeneralError(format!("failed to sign permit: {}", e)))?;
+
+        Ok(PermitSignature {
+            v: normalize_v(signature.v)?,
+            r: signature.r,
+            s: signature.s,
+        })
+    }
+
+    fn type_string(&self) -> &'static str {
+        match self.salt {
+            Some(_) => PERMIT_WITH_SALT_TYPE,
+            None => PERMIT_TYPE,
+        }
+    }
+}
+
+impl PermitSignature {
+    /// Returns the EIP-2098 compact form: `r` followed by `s` with the recovery bit in the top bit.
+    pub fn to_compact(&self) -> [u8; 64] {
+        let mut compact = [0; 64];
+        compact[..32].copy_from_slice(self.r.as_bytes());
+        compact[32..].copy_from_slice(self.s.as_bytes());
+        if self.v == 28 {
+            compact[32] |= 0x80;
+        }
+        compact
+    }
+
+    /// Formats the signature as expected in `TransactionRequest::deposit_signature`:
+    /// compact form encoded as hex without the `0x` prefix. The relayer expands it
+    /// back to `v` of 27 or 28.
+    pub fn to_relayer_format(&self) -> String {
+        self.to_compact()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Normalizes a recovery id (0 or 1) to the recovery value used on chain.
+fn normalize_v(v: u64) -> Result<u8, PoolError> {
+    match v {
+        0 | 1 => Ok(v as u8 + 27),
+        27 | 28 => Ok(v as u8),
+        v => Err(PoolError::GeneralError(format!(
+            "unexpected signature recovery value {}",
+            v
+        ))),
+    }
+}
+
+pub struct TokenContract<T: Transport = Http> {
+    pub contract: Contract<T>,
+    endpoint: Option<String>,
+    key: Option<SecretKey>,
+    timeout: Duration,
+}
+
+impl<T: Transport> TokenContract<T> {
+    pub fn new(address: H160, web3: Web3<T>, timeout: Duration) -> Result<Self, PoolError> {
+        let contract = Contract::from_json(web3.eth(), address, include_bytes!("token-abi.json"))?;
+
+        Ok(Self {
+            contract,
+            endpoint: None,
+            key: None,
+            timeout,
+        })
+    }
+
+    /// Sets the RPC endpoint label reported in spans, see [`crate::metrics::endpoint_label`].
+    pub fn with_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Sets the key used to sign permits.
+    pub fn with_key(mut self, key: SecretKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Address of the configured signer.
+    pub fn owner(&self) -> Result<H160, PoolError> {
+        Ok(SecretKeyRef::new(self.key()?).address())
+    }
+
+    traced! {
+        fields(
+            contract = ?self.contract.address(),
+            endpoint = self.endpoint.as_ref().map(field::display)
+        );
+
+        #[span(name = "token.balance_of")]
+        pub async fn balance_of(&self, owner: H160) -> Result<TokenAmount, PoolError> {
+            let result = self
+                .contract
+                .query("balanceOf", (owner,), None, Options::default(), None);
+            let balance: U256 = self.rpc("balanceOf", result).await?;
+            Ok(TokenAmount(balance))
+        }
+
+        #[span(name = "token.allowance")]
+        pub async fn allowance(
+            &self,
+            owner: H160,
+            spender: H160,
+        ) -> Result<TokenAmount, PoolError> {
+            let result = self.contract.query(
+                "allowance",
+                (owner, spender),
+                None,
+                Options::default(),
+                None,
+            );
+            let allowance: U256 = self.rpc("allowance", result).await?;
+            Ok(TokenAmount(allowance))
+        }
+
+        #[span(name = "token.nonces")]
+        pub async fn nonces(&self, owner: H160) -> Result<U256, PoolError> {
+            let result = self
+                .contract
+                .query("nonces", (owner,), None, Options::default(), None);
+            self.rpc("nonces", result).await
+        }
+
+        #[span(name = "token.decimals")]
+        pub async fn decimals(&self) -> Result<u8, PoolError> {
+            let result = self
+                .contract
+                .query("decimals", (), None, Options::default(), None);
+            self.rpc("decimals", result).await
+        }
+
+        #[span(name = "token.domain_separator")]
+        pub async fn domain_separator(&self) -> Result<H256, PoolError> {
+            let result =
+                self.contract
+                    .query("DOMAIN_SEPARATOR", (), None, Options::default(), None);
+            self.rpc("DOMAIN_SEPARATOR", result).await
+        }
+
+        /// Builds a salted permit for the configured signer using its current token nonce.
+        #[span(name = "token.deposit_permit")]
+        pub async fn deposit_permit(
+            &self,
+            spender: H160,
+            value: TokenAmount,
+            deadline: U256,
+            salt: H256,
+        ) -> Result<Permit, PoolError> {
+            let owner = self.owner()?;
+            let nonce = self.nonces(owner).await?;
+            Ok(Permit {
+                owner,
+                spender,
+                value,
+                nonce,
+                deadline,
+                salt: Some(salt),
+            })
+        }
+
+        #[span(name = "token.sign_permit")]
+        pub async fn sign_permit(&self, permit: &Permit) -> Result<PermitSignature, PoolError> {
+            let key = self.key()?;
+            let domain_separator = self.domain_separator().await?;
+            permit.sign(domain_separator, key)
+        }
+    }
+
+    /// Runs an `eth_call` of the contract `function` with the timeout, reporting
+    /// it to [`metrics`].
+    async fn rpc<R, E, F>(&self, function: &str, call: F) -> Result<R, PoolError>
+    where
+        F: Future<Output = Result<R, E>>,
+        PoolError: From<E>,
+    {
+        let endpoint = self.endpoint.as_deref().unwrap_or("unknown");
+        metrics::global()
+            .observe_function(TOKEN_CLIENT, "eth_call", function, endpoint, async {
+                Ok(timeout(self.timeout, call).await??)
+            })
+            .await
+    }
+
+    fn key(&self) -> Result<&SecretKey, PoolError> {
+        self.key
+            .as_ref()
+            .ok_or_else(|| PoolError::GeneralError("secret key is not configured".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use ethabi::Token;
+    use secp256k1::SecretKey;
+    use web3::{
+        signing::{recover, Key, SecretKeyRef},
+        types::{H160, H256, U256},
+        Web3,
+    };
+
+    use crate::amount::TokenAmount;
+
+    use super::{
+        super::mock::{EthCall, MockTransport},
+        normalize_v, Permit, PermitSignature, TokenContract,
+    };
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn token(transport: MockTransport) -> TokenContract<MockTransport> {
+        TokenContract::new(
+            H160::repeat_byte(0x03),
+            Web3::new(transport),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    /// Token where `0x0a..0a` holds 5 tokens, allows `0x0b..0b` 2 of them and
+    /// signed 7 permits.
+    fn token_transport() -> MockTransport {
+        let abi = token(MockTransport::new(|_, _| None))
+            .contract
+            .abi()
+            .clone();
+        let owner = Token::Address(H160::repeat_byte(0x0a));
+        MockTransport::calls(move |call: &EthCall| match call.function(&abi)? {
+            ("balanceOf", args) if args == vec![owner.clone()] => {
+                Some(vec![Token::Uint(U256::from(5) * U256::exp10(18))])
+            }
+            ("allowance", args)
+                if args == vec![owner.clone(), Token::Address(H160::repeat_byte(0x0b))] =>
+            {
+                Some(vec![Token::Uint(U256::from(2) * U256::exp10(18))])
+            }
+            ("nonces", _) => Some(vec![Token::Uint(U256::from(7))]),
+            ("decimals", _) => Some(vec![Token::Uint(U256::from(18))]),
+            ("DOMAIN_SEPARATOR", _) => Some(vec![Token::FixedBytes(vec![0x33; 32])]),
+            _ => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn token_state_is_read() {
+        let token = token(token_transport());
+        let owner = H160::repeat_byte(0x0a);
+
+        assert_eq!(
+            token.balance_of(owner).await.unwrap(),
+            TokenAmount(U256::from(5) * U256::exp10(18))
+        );
+        assert_eq!(
+            token
+                .allowance(owner, H160::repeat_byte(0x0b))
+                .await
+                .unwrap(),
+            TokenAmount(U256::from(2) * U256::exp10(18))
+        );
+        assert_eq!(token.nonces(owner).await.unwrap(), U256::from(7));
+        assert_eq!(token.decimals().await.unwrap(), 18);
+        assert_eq!(
+            token.domain_separator().await.unwrap(),
+            H256::repeat_byte(0x33)
+        );
+    }
+
+    #[tokio::test]
+    async fn deposit_permit_is_signed_by_owner() {
+        let key = SecretKey::from_str(KEY).unwrap();
+        let token = token(token_transport()).with_key(key);
+        let salt = H256::repeat_byte(0x22);
+
+        let permit = token
+            .deposit_permit(
+                H160::repeat_byte(0x11),
+                TokenAmount(U256::exp10(18)),
+                U256::from(1_700_000_000u64),
+                salt,
+            )
+            .await
+            .unwrap();
+        assert_eq!(permit.owner, SecretKeyRef::new(&key).address());
+        assert_eq!(permit.nonce, U256::from(7));
+        assert_eq!(permit.salt, Some(salt));
+
+        let signature = token.sign_permit(&permit).await.unwrap();
+        assert_eq!(
+            signature,
+            permit.sign(H256::repeat_byte(0x33), &key).unwrap()
+        );
+    }
+
+    #[test]
+    fn signed_permit_recovers_owner() {
+        let key = SecretKey::from_str(KEY).unwrap();
+        let permit = Permit {
+            owner: SecretKeyRef::new(&key).address(),
+            spender: H160::repeat_byte(0x11),
+            value: TokenAmount(U256::exp10(18)),
+            nonce: U256::zero(),
+            deadline: U256::from(1_700_000_000u64),
+            salt: Some(H256::repeat_byte(0x22)),
+        };
+        let domain_separator = H256::repeat_byte(0x33);
+
+        let signature = permit.sign(domain_separator, &key).unwrap();
+        assert!(signature.v == 27 || signature.v == 28);
+        let digest = permit.digest(domain_separator);
+        let mut rs = [0; 64];
+        rs[..32].copy_from_slice(signature.r.as_bytes());
+        rs[32..].copy_from_slice(signature.s.as_bytes());
+
+        let signer = recover(digest.as_bytes(), &rs, signature.v as i32 - 27).unwrap();
+        assert_eq!(signer, permit.owner);
+
+        let compact = signature.to_relayer_format();
+        assert_eq!(compact.len(), 128);
+        let s_high_byte = u8::from_str_radix(&compact[64..66], 16).unwrap();
+        assert_eq!(s_high_byte >> 7, signature.v - 27);
+    }
+
+    #[test]
+    fn recovery_value_is_normalized() {
+        assert_eq!(normalize_v(0).unwrap(), 27);
+        assert_eq!(normalize_v(1).unwrap(), 28);
+        assert_eq!(normalize_v(27).unwrap(), 27);
+        assert_eq!(normalize_v(28).unwrap(), 28);
+        assert!(normalize_v(2).is_err());
+
+        let signature = |v| PermitSignature {
+            v,
+            r: H256::repeat_byte(0x01),
+            s: H256::repeat_byte(0x02),
+        };
+        assert_eq!(signature(27).to_compact()[32], 0x02);
+        assert_eq!(signature(28).to_compact()[32], 0x82);
+    }
+}
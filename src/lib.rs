@@ -1,10 +1,14 @@
 use libzeropool::{native::params::PoolBN256, fawkes_crypto::{backend::bellman_groth16::engines::Bn256, engines::bn256}};
 pub use tracing;
 
+pub mod amount;
 pub mod configuration;
 pub mod contracts;
+pub mod metrics;
 pub mod telemetry;
 pub mod relayer;
+pub mod transport;
+pub mod version;
 
 pub type PoolParams = PoolBN256;
 pub type Engine = Bn256;
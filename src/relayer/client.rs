@@ -1,15 +1,29 @@
-use reqwest::{Client, Response};
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
+use reqwest::{header::HeaderMap, Client, Response, Url};
 use serde::{de::DeserializeOwned, Serialize};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{
+    amount::PoolAmount,
+    metrics::{self, RELAYER_CLIENT},
+    telemetry::span::traced,
+};
 
 use super::{
     error::RelayerError,
-    types::{FeeResponse, InfoResponse, JobResponse, TransactionRequest, TransactionResponse},
+    types::{
+        FeeResponse, InfoResponse, JobResponse, LimitsResponse, TransactionRequest,
+        TransactionResponse,
+    },
 };
 
 pub const LIB_VERSION: &str = "2.0.2";
 
 pub struct RelayerClient {
     url: String,
+    endpoint: String,
     client: Client,
 }
 
@@ -17,62 +31,128 @@ impl RelayerClient {
     pub fn new(url: &str) -> Result<RelayerClient, RelayerError> {
         Ok(RelayerClient {
             url: url.to_string(),
+            endpoint: metrics::endpoint_label(url),
             client: Client::new()
         })
     }
 
-    pub async fn info(&self) -> Result<InfoResponse, RelayerError> {
-        self.get("info").await
-    }
+    traced! {
+        fields(endpoint = %self.endpoint);
 
-    pub async fn transactions(&self, offset: u64, limit: u64) -> Result<Vec<String>, RelayerError> {
-        self.get(&format!("transactions/v2?limit={limit}&offset={offset}"))
+        #[span(name = "relayer.info")]
+        pub async fn info(&self) -> Result<InfoResponse, RelayerError> {
+            let info: InfoResponse = self.get("info", "info").await?;
+            metrics::global().set_relayer_delta_lag(
+                &self.endpoint,
+                info.optimistic_delta_index as i64 - info.delta_index as i64,
+            );
+            Ok(info)
+        }
+
+        #[span(name = "relayer.transactions")]
+        pub async fn transactions(
+            &self,
+            offset: u64,
+            limit: u64,
+        ) -> Result<Vec<String>, RelayerError> {
+            self.get(
+                "transactions",
+                &format!("transactions/v2?limit={limit}&offset={offset}"),
+            )
             .await
-    }
+        }
 
-    pub async fn send_transactions(
-        &self,
-        request: Vec<TransactionRequest>,
-    ) -> Result<TransactionResponse, RelayerError> {
-        self.post("sendTransactions", request).await
+        #[span(
+            name = "relayer.send_transactions",
+            skip(request),
+            fields(transactions = request.len()),
+        )]
+        pub async fn send_transactions(
+            &self,
+            request: Vec<TransactionRequest>,
+        ) -> Result<TransactionResponse, RelayerError> {
+            self.post("send_transactions", "sendTransactions", request)
+                .await
+        }
+
+        #[span(name = "relayer.job")]
+        pub async fn job(&self, id: &str) -> Result<JobResponse, RelayerError> {
+            self.get("job", &format!("job/{}", id)).await
+        }
+
+        #[span(name = "relayer.fee")]
+        pub async fn fee(&self) -> Result<PoolAmount, RelayerError> {
+            let fee: FeeResponse = self.get("fee", "fee").await?;
+            fee.fee
+                .parse::<PoolAmount>()
+                .map_err(|err| RelayerError::UnknownError(format!("failed to parse fee: {}", err)))
+        }
+
+        #[span(name = "relayer.limits")]
+        pub async fn limits(&self, address: Option<&str>) -> Result<LimitsResponse, RelayerError> {
+            let mut url = self.endpoint_url("limits")?;
+            if let Some(address) = address {
+                url.query_pairs_mut().append_pair("address", address);
+            }
+            self.get_url("limits", url).await
+        }
     }
 
-    pub async fn job(&self, id: &str) -> Result<JobResponse, RelayerError> {
-        self.get(&format!("job/{}", id)).await
+    /// `{url}/{query}` of the relayer.
+    fn endpoint_url(&self, query: &str) -> Result<Url, RelayerError> {
+        Ok(Url::parse(&format!("{}/{}", self.url, query))?)
     }
 
-    pub async fn fee(&self) -> Result<u64, RelayerError> {
-        let fee: FeeResponse = self.get("fee").await?;
-        fee.fee
-            .parse::<u64>()
-            .map_err(|err| RelayerError::UnknownError(format!("failed to parse fee: {}", err)))
+    /// Sends `GET {url}/{query}`, reporting it to [`metrics`] as `method`.
+    async fn get<T: DeserializeOwned>(&self, method: &str, query: &str) -> Result<T, RelayerError> {
+        self.get_url(method, self.endpoint_url(query)?).await
     }
 
-    async fn get<T: DeserializeOwned>(&self, query: &str) -> Result<T, RelayerError> {
-        let response = self.client
-            .get(format!("{}/{}", self.url, query))
-            .header("zkbob-support-id", "zkbob-utils-rs")
-            .header("zkbob-libjs-version", LIB_VERSION)
-            .send()
-            .await?;
+    /// Sends `GET url`, reporting it to [`metrics`] as `method`.
+    async fn get_url<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        url: Url,
+    ) -> Result<T, RelayerError> {
+        let request = async {
+            let response = self
+                .client
+                .get(url)
+                .headers(trace_context())
+                .header("zkbob-support-id", "zkbob-utils-rs")
+                .header("zkbob-libjs-version", LIB_VERSION)
+                .send()
+                .await?;
 
-        self.handle_response(response).await
+            self.handle_response(response).await
+        };
+        metrics::global()
+            .observe(RELAYER_CLIENT, method, &self.endpoint, request)
+            .await
     }
 
     async fn post<Request: Serialize, Response: DeserializeOwned>(
         &self,
+        method: &str,
         query: &str,
         request: Request,
     ) -> Result<Response, RelayerError> {
-        let response = self.client
-            .post(format!("{}/{}", self.url, query))
-            .json(&request)
-            .header("zkbob-support-id", "zkbob-utils-rs")
-            .header("zkbob-libjs-version", LIB_VERSION)
-            .send()
-            .await?;
-
-        self.handle_response(response).await
+        let request = async {
+            let response = self
+                .client
+                .post(format!("{}/{}", self.url, query))
+                .headers(trace_context())
+                .json(&request)
+                .header("zkbob-support-id", "zkbob-utils-rs")
+                .header("zkbob-libjs-version", LIB_VERSION)
+                .send()
+                .await?;
+
+            self.handle_response(response).await
+        };
+        metrics::global()
+            .observe(RELAYER_CLIENT, method, &self.endpoint, request)
+            .await
     }
 
     async fn handle_response<T: DeserializeOwned>(
@@ -89,10 +169,117 @@ impl RelayerClient {
     }
 }
 
+/// Headers carrying the context of the current span, e.g. `traceparent`, as
+/// encoded by the global propagator installed by [`crate::telemetry`].
+fn trace_context() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
 #[cfg(test)]
 mod tests {
+    use opentelemetry::{
+        global,
+        sdk::trace::TracerProvider,
+        trace::{noop::NoopTextMapPropagator, TracerProvider as _},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use crate::{configuration::Propagator, telemetry::telemetry::propagator};
+
     use super::RelayerClient;
 
+    /// Restores the default global propagator when dropped.
+    struct PropagatorGuard;
+
+    impl Drop for PropagatorGuard {
+        fn drop(&mut self) {
+            global::set_text_map_propagator(NoopTextMapPropagator::new());
+        }
+    }
+
+    /// Answers one request at the returned url with `body`, resolving to the
+    /// lowercased request head.
+    async fn serve_once(body: &str) -> (String, JoinHandle<String>) {
+        let body = body.to_string();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let request = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let mut chunk = [0; 4096];
+                let n = stream.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf).to_lowercase()
+        });
+        (url, request)
+    }
+
+    #[tokio::test]
+    async fn trace_context_is_propagated() {
+        let (url, request) = serve_once(
+            r#"{"root":"0","optimisticRoot":"0","deltaIndex":128,"optimisticDeltaIndex":256}"#,
+        )
+        .await;
+
+        global::set_text_map_propagator(propagator(&[
+            Propagator::TraceContext,
+            Propagator::Jaeger,
+        ]));
+        let _propagator = PropagatorGuard;
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = RelayerClient::new(&url).unwrap();
+        let info = client.info().await.unwrap();
+        assert_eq!(info.optimistic_delta_index - info.delta_index, 128);
+
+        let headers = request.await.unwrap();
+        assert!(headers.contains("traceparent: 00-"), "{}", headers);
+        assert!(headers.contains("uber-trace-id: "), "{}", headers);
+    }
+
+    #[tokio::test]
+    async fn limits_address_is_encoded() {
+        let limit = r#"{"total":"0","available":"0"}"#;
+        let body = format!(
+            r#"{{"deposit":{{"singleOperation":"1","dailyForAddress":{0},"dailyForAll":{0},"poolLimit":{0}}},"withdraw":{{"dailyForAll":{0}}}}}"#,
+            limit
+        );
+        let (url, request) = serve_once(&body).await;
+
+        let client = RelayerClient::new(&url).unwrap();
+        let limits = client.limits(Some("0x01&tier=2")).await.unwrap();
+        assert_eq!(limits.deposit.single_operation.as_u64(), 1);
+
+        let head = request.await.unwrap();
+        assert!(
+            head.starts_with("get /limits?address=0x01%26tier%3d2 "),
+            "{}",
+            head
+        );
+    }
+
     #[tokio::test]
     #[ignore = "the test requires working relayer"]
     async fn info_request() {
@@ -1,8 +1,10 @@
+use std::fmt;
+
 use libzeropool::fawkes_crypto::{ff_uint::Num, backend::bellman_groth16::prover};
 use serde::{Serialize, Deserialize};
+use serde_aux::field_attributes::deserialize_number_from_string;
 
-use crate::{Engine, Fr};
-
+use crate::{amount::PoolAmount, configuration::Secret, Engine, Fr};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -22,6 +24,15 @@ pub struct Proof {
     pub proof: prover::Proof<Engine>,
 }
 
+impl fmt::Debug for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proof")
+            .field("inputs", &self.inputs.len())
+            .field("proof", &Secret::new(&self.proof))
+            .finish()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TransactionRequest {
@@ -32,6 +43,21 @@ pub struct TransactionRequest {
     pub deposit_signature: Option<String>,
 }
 
+impl fmt::Debug for TransactionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionRequest")
+            .field("uuid", &self.uuid)
+            .field("proof", &self.proof)
+            .field("memo", &Secret::new(&self.memo))
+            .field("tx_type", &self.tx_type)
+            .field(
+                "deposit_signature",
+                &self.deposit_signature.as_ref().map(Secret::new),
+            )
+            .finish()
+    }
+}
+
 #[derive(Serialize,Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct TransactionResponse {
@@ -52,4 +78,33 @@ pub struct JobResponse {
 #[serde(rename_all = "camelCase")]
 pub struct FeeResponse {
     pub fee: String,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Limit {
+    pub total: PoolAmount,
+    pub available: PoolAmount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct DepositLimits {
+    pub single_operation: PoolAmount,
+    pub daily_for_address: Limit,
+    pub daily_for_all: Limit,
+    pub pool_limit: Limit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawLimits {
+    pub daily_for_all: Limit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LimitsResponse {
+    pub deposit: DepositLimits,
+    pub withdraw: WithdrawLimits,
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub tier: u64,
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use opentelemetry::trace::TraceError;
+use tracing_bunyan_formatter::SkipFieldError;
+use tracing_subscriber::{filter::ParseError, util::TryInitError};
+
+#[derive(Debug)]
+pub enum TelemetryError {
+    /// A global subscriber is already installed, e.g. `setup` was called twice.
+    AlreadyInitialized,
+    /// The subscriber or the `log` adapter could not be installed.
+    Init(TryInitError),
+    /// The span exporter could not be built.
+    Exporter(TraceError),
+    Formatter(SkipFieldError),
+    /// A log directive is malformed.
+    Filter(ParseError),
+    /// A log file could not be opened.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::AlreadyInitialized => f.write_str("telemetry is already initialized"),
+            TelemetryError::Init(err) => write!(f, "failed to init telemetry: {}", err),
+            TelemetryError::Exporter(err) => write!(f, "failed to init span exporter: {}", err),
+            TelemetryError::Formatter(err) => write!(f, "failed to init log formatter: {}", err),
+            TelemetryError::Filter(err) => write!(f, "invalid log directive: {}", err),
+            TelemetryError::Io(err) => write!(f, "failed to open log file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {}
+
+impl From<TryInitError> for TelemetryError {
+    fn from(e: TryInitError) -> Self {
+        TelemetryError::Init(e)
+    }
+}
+
+impl From<SkipFieldError> for TelemetryError {
+    fn from(e: SkipFieldError) -> Self {
+        TelemetryError::Formatter(e)
+    }
+}
+
+impl From<ParseError> for TelemetryError {
+    fn from(e: ParseError) -> Self {
+        TelemetryError::Filter(e)
+    }
+}
+
+impl From<std::io::Error> for TelemetryError {
+    fn from(e: std::io::Error) -> Self {
+        TelemetryError::Io(e)
+    }
+}
+
+impl From<TraceError> for TelemetryError {
+    fn from(e: TraceError) -> Self {
+        TelemetryError::Exporter(e)
+    }
+}
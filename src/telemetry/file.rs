@@ -0,0 +1,434 @@
+//! Log files for hosts without log shipping.
+//!
+//! [`RollingFile`] starts a new file daily and/or once a size limit is reached,
+//! renaming the old one to `<name>.<date>` (`<name>.<date>.<n>` if taken) and
+//! removing the oldest rotated files beyond the retention count. [`non_blocking`]
+//! moves the writes to a background thread so that logging never waits on disk.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use time::{Date, Month, OffsetDateTime};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::configuration::FileRotation;
+
+/// Lines buffered by [`non_blocking`] before new ones are dropped.
+pub const DEFAULT_BUFFERED_LINES: usize = 128_000;
+
+pub struct RollingFile {
+    path: PathBuf,
+    rotation: FileRotation,
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+    file: File,
+    size: u64,
+    /// Day of the entries in the current file.
+    day: Date,
+}
+
+impl RollingFile {
+    /// Opens `path` for appending, creating it and its directory if needed.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        rotation: FileRotation,
+        max_size: Option<u64>,
+        max_files: Option<usize>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        if path.file_name().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file path", path.display()),
+            ));
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = append(&path)?;
+        let metadata = file.metadata()?;
+        let day = metadata
+            .modified()
+            .map(|modified| OffsetDateTime::from(modified).date())
+            .unwrap_or_else(|_| today());
+
+        Ok(Self {
+            path,
+            rotation,
+            max_size,
+            max_files,
+            file,
+            size: metadata.len(),
+            day,
+        })
+    }
+
+    fn write_at(&mut self, today: Date, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(today, buf.len()) {
+            // keep writing to the current file rather than losing lines
+            if let Err(err) = self.rotate() {
+                eprintln!("failed to rotate {}: {}", self.path.display(), err);
+            }
+        }
+        self.day = today;
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn should_rotate(&self, today: Date, len: usize) -> bool {
+        let new_day = self.rotation == FileRotation::Daily && today != self.day;
+        let full = matches!(self.max_size, Some(max) if self.size + len as u64 > max);
+        self.size > 0 && (new_day || full)
+    }
+
+    /// Renames the current file and starts a new one. If renaming fails, the
+    /// current file is reopened and its size limit starts over.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let renamed = self
+            .rotated_path()
+            .and_then(|rotated| fs::rename(&self.path, rotated));
+        self.file = append(&self.path)?;
+        self.size = 0;
+        renamed?;
+        self.prune()
+    }
+
+    /// `<name>.<date>` for the first file rotated on a day, `<name>.<date>.<n>`
+    /// for the following ones.
+    fn rotated_path(&self) -> io::Result<PathBuf> {
+        let n = self
+            .rotated_files()?
+            .into_iter()
+            .filter(|(day, _, _)| *day == self.day)
+            .map(|(_, n, _)| n + 1)
+            .max()
+            .unwrap_or(0);
+        let mut name = self.file_name().to_os_string();
+        name.push(format!(
+            ".{}-{:02}-{:02}",
+            self.day.year(),
+            self.day.month() as u8,
+            self.day.day()
+        ));
+        if n > 0 {
+            name.push(format!(".{}", n));
+        }
+        Ok(self.path.with_file_name(name))
+    }
+
+    /// Removes the oldest rotated files beyond `max_files`.
+    fn prune(&self) -> io::Result<()> {
+        let max_files = match self.max_files {
+            Some(max_files) => max_files,
+            None => return Ok(()),
+        };
+        let mut rotated = self.rotated_files()?;
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(max_files);
+        for (_, _, path) in rotated.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Day and number of the files rotated from this one, other files in the
+    /// directory are left alone.
+    fn rotated_files(&self) -> io::Result<Vec<(Date, u32, PathBuf)>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = self.file_name().to_string_lossy();
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let suffix = file_name
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(name.as_ref()))
+                .and_then(|suffix| suffix.strip_prefix('.'));
+            if let Some((day, n)) = suffix.and_then(parse_rotated) {
+                rotated.push((day, n, entry.path()));
+            }
+        }
+        Ok(rotated)
+    }
+
+    fn file_name(&self) -> &std::ffi::OsStr {
+        self.path.file_name().expect("checked in open")
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(today(), buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn today() -> Date {
+    OffsetDateTime::now_utc().date()
+}
+
+/// Parses the `YYYY-MM-DD[.N]` suffix of a rotated file.
+fn parse_rotated(suffix: &str) -> Option<(Date, u32)> {
+    let (date, n) = match suffix.split_once('.') {
+        Some((date, n)) => (date, number(n)?),
+        None => (suffix, 0),
+    };
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if (year.len(), month.len(), day.len()) != (4, 2, 2) {
+        return None;
+    }
+    let month = Month::try_from(number(month)? as u8).ok()?;
+    let date = Date::from_calendar_date(number(year)? as i32, month, number(day)? as u8).ok()?;
+    Some((date, n))
+}
+
+fn number(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+enum Message {
+    Line(Vec<u8>),
+    Shutdown,
+}
+
+/// Writer handing lines to the background thread of [`non_blocking`].
+///
+/// Lines are dropped while the thread is `buffered_lines` behind.
+#[derive(Clone)]
+pub struct NonBlocking {
+    sender: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl NonBlocking {
+    /// Number of lines dropped so far because the buffer was full.
+    pub fn dropped_lines(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Write for NonBlocking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.sender.try_send(Message::Line(buf.to_vec())) {
+            Ok(()) => Ok(buf.len()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(buf.len())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "log writer thread has stopped",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for NonBlocking {
+    type Writer = NonBlocking;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Writes the remaining lines and stops the background thread of
+/// [`non_blocking`] when dropped.
+#[must_use = "buffered lines are written and the thread is stopped when the guard is dropped"]
+pub struct WriterGuard {
+    sender: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WriterGuard {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!("log writer dropped {} lines", dropped);
+        }
+    }
+}
+
+impl std::fmt::Debug for WriterGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriterGuard").finish_non_exhaustive()
+    }
+}
+
+/// Moves writes to `writer` to a background thread buffering up to
+/// `buffered_lines` lines.
+pub fn non_blocking<W: Write + Send + 'static>(
+    writer: W,
+    buffered_lines: usize,
+) -> io::Result<(NonBlocking, WriterGuard)> {
+    let (sender, receiver) = mpsc::sync_channel(buffered_lines);
+    let thread = thread::Builder::new()
+        .name("log-writer".to_string())
+        .spawn(move || write_lines(writer, receiver))?;
+    let dropped = Arc::new(AtomicU64::new(0));
+
+    let writer = NonBlocking {
+        sender: sender.clone(),
+        dropped: dropped.clone(),
+    };
+    let guard = WriterGuard {
+        sender,
+        dropped,
+        thread: Some(thread),
+    };
+    Ok((writer, guard))
+}
+
+fn write_lines<W: Write>(mut writer: W, lines: Receiver<Message>) {
+    for message in lines {
+        match message {
+            Message::Line(line) => {
+                if let Err(err) = writer.write_all(&line) {
+                    eprintln!("failed to write log line: {}", err);
+                }
+            }
+            Message::Shutdown => break,
+        }
+    }
+    if let Err(err) = writer.flush() {
+        eprintln!("failed to flush log file: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use time::{Date, Month};
+
+    use super::{non_blocking, RollingFile};
+    use crate::configuration::FileRotation;
+
+    fn log_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zkbob-log-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn files_are_rotated_by_size_and_pruned() {
+        let dir = log_dir("size");
+        let path = dir.join("service.log");
+        let mut file = RollingFile::open(&path, FileRotation::Never, Some(10), Some(2)).unwrap();
+        for other in [
+            "service.log.keep",
+            "service.log.2020-01-01.bak",
+            "other.log.2020-01-01",
+        ] {
+            fs::write(dir.join(other), "other\n").unwrap();
+        }
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        let mut rotated = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("service.log.2") && !name.ends_with(".bak"))
+            .collect::<Vec<_>>();
+        rotated.sort();
+        assert_eq!(rotated.len(), 2);
+        for other in [
+            "service.log.keep",
+            "service.log.2020-01-01.bak",
+            "other.log.2020-01-01",
+        ] {
+            assert!(dir.join(other).exists(), "{} was removed", other);
+        }
+        let contents = rotated
+            .iter()
+            .map(|name| fs::read_to_string(dir.join(name)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(contents.contains(&"second\n".to_string()));
+        assert!(contents.contains(&"third\n".to_string()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_rotated_daily_and_pruned_in_order() {
+        let dir = log_dir("daily");
+        let path = dir.join("service.log");
+        let mut file = RollingFile::open(&path, FileRotation::Daily, Some(4), Some(3)).unwrap();
+        let day = |day| Date::from_calendar_date(2030, Month::January, day).unwrap();
+        file.write_at(day(1), b"day\n").unwrap();
+        for i in 0..12 {
+            file.write_at(day(2), format!("{:02}\n", i).as_bytes())
+                .unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "11\n");
+        let mut rotated = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != "service.log")
+            .map(|name| (fs::read_to_string(dir.join(&name)).unwrap(), name))
+            .collect::<Vec<_>>();
+        rotated.sort();
+        assert_eq!(
+            rotated,
+            [
+                ("08\n".to_string(), "service.log.2030-01-02.8".to_string()),
+                ("09\n".to_string(), "service.log.2030-01-02.9".to_string()),
+                ("10\n".to_string(), "service.log.2030-01-02.10".to_string()),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lines_are_written_before_guard_is_dropped() {
+        let dir = log_dir("non-blocking");
+        let path = dir.join("service.log");
+        let file = RollingFile::open(&path, FileRotation::Daily, None, None).unwrap();
+        let (mut writer, guard) = non_blocking(file, 16).unwrap();
+        for i in 0..10 {
+            writer
+                .write_all(format!("line {}\n", i).as_bytes())
+                .unwrap();
+        }
+        drop(guard);
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 10);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
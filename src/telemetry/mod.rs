@@ -1 +1,7 @@
+pub mod error;
+pub mod file;
+pub mod otlp;
+pub mod redact;
+pub mod sampler;
+pub mod span;
 pub mod telemetry;
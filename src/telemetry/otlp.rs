@@ -0,0 +1,243 @@
+use std::{collections::HashMap, io::Write, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use http::{header::CONTENT_ENCODING, HeaderValue, Request, Response};
+use opentelemetry::{global, runtime::Tokio, sdk::trace, trace::TraceError, KeyValue};
+use opentelemetry_http::{HttpClient, HttpError};
+use opentelemetry_otlp::WithExportConfig;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
+
+use crate::configuration::{
+    OtlpCompression, OtlpProtocol, OtlpSettings, Propagator, SamplingSettings,
+};
+
+use super::{
+    error::TelemetryError,
+    sampler,
+    telemetry::{ensure_uninitialized, propagator, try_init, TelemetryGuard},
+};
+
+pub const DEFAULT_GRPC_ENDPOINT: &str = "http://localhost:4317";
+pub const DEFAULT_HTTP_ENDPOINT: &str = "http://localhost:4318/v1/traces";
+
+pub fn init_otlp(
+    name: String,
+    log_level: String,
+    endpoint: &Option<String>,
+    settings: &OtlpSettings,
+    resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+
+    let tracer = tracer(name, endpoint, settings, resource, sampling)?;
+    let guard = TelemetryGuard::new(true);
+
+    let (env_filter, filter) = reload::Layer::new(EnvFilter::new(log_level));
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer().with_target(false))
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    try_init(subscriber, vec![(None, filter)], &[])?;
+    global::set_text_map_propagator(propagator(&[Propagator::TraceContext]));
+    Ok(guard)
+}
+
+/// Installs a batch OTLP span exporter and returns its tracer.
+pub fn tracer(
+    name: String,
+    endpoint: &Option<String>,
+    settings: &OtlpSettings,
+    mut resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+) -> Result<trace::Tracer, TraceError> {
+    resource.push(KeyValue::new("service.name", name));
+    let pipeline = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_trace_config(sampler::trace_config(resource, sampling));
+    let timeout = Duration::from_secs(settings.timeout_sec);
+
+    let pipeline = match settings.protocol {
+        OtlpProtocol::Grpc => {
+            let mut metadata = MetadataMap::new();
+            for (name, value) in &settings.headers {
+                let key = MetadataKey::from_bytes(name.as_bytes())
+                    .map_err(|err| TraceError::Other(Box::new(err)))?;
+                let value = MetadataValue::try_from(value.expose().as_str())
+                    .map_err(|err| TraceError::Other(Box::new(err)))?;
+                metadata.insert(key, value);
+            }
+            let exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.as_deref().unwrap_or(DEFAULT_GRPC_ENDPOINT))
+                .with_timeout(timeout)
+                .with_metadata(metadata);
+            pipeline.with_exporter(exporter)
+        }
+        OtlpProtocol::Http => {
+            let headers = settings
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.expose().clone()))
+                .collect::<HashMap<_, _>>();
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint.as_deref().unwrap_or(DEFAULT_HTTP_ENDPOINT))
+                .with_timeout(timeout)
+                .with_headers(headers);
+            let exporter = match settings.compression {
+                Some(OtlpCompression::Gzip) => {
+                    exporter.with_http_client(GzipHttpClient(reqwest::Client::new()))
+                }
+                None => exporter.with_http_client(reqwest::Client::new()),
+            };
+            pipeline.with_exporter(exporter)
+        }
+    };
+
+    pipeline.install_batch(Tokio)
+}
+
+/// Compresses request bodies before passing them to the inner client.
+#[derive(Debug)]
+pub struct GzipHttpClient<C>(pub C);
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for GzipHttpClient<C> {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let (mut parts, body) = request.into_parts();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body)?;
+        let body = encoder.finish()?;
+        parts
+            .headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        self.0.send(Request::from_parts(parts, body)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Read};
+
+    use flate2::read::GzDecoder;
+    use http::Request;
+    use opentelemetry::{global, trace::Tracer};
+    use opentelemetry_http::HttpClient;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::configuration::{
+        OtlpCompression, OtlpProtocol, OtlpSettings, SamplingSettings, Secret,
+    };
+
+    use super::{tracer, GzipHttpClient};
+
+    /// Accepts a single HTTP request and returns its headers and body.
+    async fn collector_stub() -> (String, tokio::task::JoinHandle<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let request = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let (headers, body_start, content_length) = loop {
+                let mut chunk = [0; 4096];
+                let n = stream.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+                if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let headers = String::from_utf8_lossy(&buf[..pos]).to_lowercase();
+                    let content_length = headers
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .map(|len| len.trim().parse::<usize>().unwrap())
+                        .unwrap_or(0);
+                    break (headers, pos + 4, content_length);
+                }
+            };
+            while buf.len() < body_start + content_length {
+                let mut chunk = [0; 4096];
+                let n = stream.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            (
+                headers,
+                buf[body_start..body_start + content_length].to_vec(),
+            )
+        });
+        (url, request)
+    }
+
+    #[tokio::test]
+    async fn gzip_client_compresses_body() {
+        let (url, request) = collector_stub().await;
+        let client = GzipHttpClient(reqwest::Client::new());
+
+        let response = client
+            .send(
+                Request::post(url)
+                    .header("x-api-key", "secret")
+                    .body(b"spans".repeat(100))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let (headers, body) = request.await.unwrap();
+        assert!(headers.contains("content-encoding: gzip"));
+        assert!(headers.contains("x-api-key: secret"));
+        let mut decoded = Vec::new();
+        GzDecoder::new(body.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"spans".repeat(100));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn http_pipeline_exports_to_endpoint() {
+        let (url, request) = collector_stub().await;
+        let settings = OtlpSettings {
+            protocol: OtlpProtocol::Http,
+            headers: HashMap::from([("x-api-key".to_string(), Secret::new("secret".to_string()))]),
+            compression: Some(OtlpCompression::Gzip),
+            timeout_sec: 5,
+        };
+
+        let tracer = tracer(
+            "test".to_string(),
+            &Some(url),
+            &settings,
+            Vec::new(),
+            &SamplingSettings::default(),
+        )
+        .unwrap();
+        tracer.in_span("exported", |_| {});
+        let provider = tracer.provider().unwrap();
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let (headers, body) = request.await.unwrap();
+        tokio::task::spawn_blocking(global::shutdown_tracer_provider)
+            .await
+            .unwrap();
+        assert!(headers.starts_with("post /v1/traces "));
+        assert!(headers.contains("content-type: application/x-protobuf"));
+        assert!(headers.contains("content-encoding: gzip"));
+        assert!(headers.contains("x-api-key: secret"));
+        let mut decoded = Vec::new();
+        GzDecoder::new(body.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(String::from_utf8_lossy(&decoded).contains("exported"));
+    }
+}
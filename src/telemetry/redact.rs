@@ -0,0 +1,527 @@
+//! Redaction of sensitive values before logs are formatted or spans exported.
+//!
+//! [`RedactingLayer`] wraps the layers of all outputs and hands them copies of
+//! spans and events with redacted values, so that e.g. a secret key recorded
+//! through `Debug` never reaches a formatter or an exporter.
+//!
+//! Field names are only matched at the top level of a span or event. Values
+//! nested in other types, e.g. the memo of a
+//! [`crate::relayer::types::TransactionRequest`], are kept out by the `Debug`
+//! implementation of their type.
+
+use std::{any::TypeId, borrow::Cow, fmt};
+
+use tracing::{
+    field::{self, DisplayValue, Field, FieldSet, Value, ValueSet, Visit},
+    span, Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+use crate::configuration::RedactionSettings;
+
+/// Replacement of redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Values passed on per redacted span or event, see [`with_value_set`].
+const MAX_FIELDS: usize = 64;
+
+/// Length of a hex encoded private key, the shortest run that is redacted.
+const KEY_HEX_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    fields: Vec<String>,
+    hex_keys: bool,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Self {
+        Self {
+            fields: settings
+                .fields
+                .iter()
+                .map(|field| field.to_lowercase())
+                .collect(),
+            hex_keys: settings.hex_keys,
+        }
+    }
+
+    /// Whether nothing would be redacted.
+    pub fn is_noop(&self) -> bool {
+        self.fields.is_empty() && !self.hex_keys
+    }
+
+    /// Wraps `inner` so that it only sees redacted values.
+    pub fn layer<L>(&self, inner: L) -> RedactingLayer<L> {
+        RedactingLayer {
+            inner,
+            redactor: self.clone(),
+        }
+    }
+
+    fn redacts_field(&self, field: &Field) -> bool {
+        self.fields
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(field.name()))
+    }
+
+    /// Whether values of spans or events of `metadata` may need redaction.
+    fn inspects(&self, metadata: &Metadata<'_>) -> bool {
+        self.hex_keys
+            || metadata
+                .fields()
+                .iter()
+                .any(|field| self.redacts_field(&field))
+    }
+
+    /// Replaces every run of at least 64 hex digits in `value`, e.g. private
+    /// keys and signatures.
+    pub fn redact_str<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if !self.hex_keys {
+            return Cow::Borrowed(value);
+        }
+        let mut redacted = String::new();
+        let mut copied = 0;
+        let mut run_start = None;
+        for (i, c) in value.char_indices().chain([(value.len(), ' ')]) {
+            match (c.is_ascii_hexdigit(), run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    if i - start >= KEY_HEX_LEN {
+                        redacted.push_str(&value[copied..start]);
+                        redacted.push_str(REDACTED);
+                        copied = i;
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        if copied == 0 {
+            return Cow::Borrowed(value);
+        }
+        redacted.push_str(&value[copied..]);
+        Cow::Owned(redacted)
+    }
+
+    /// Values recorded by `record`, `None` if none of them needs redaction.
+    fn collect(&self, record: impl FnOnce(&mut dyn Visit)) -> Option<Vec<(Field, Owned)>> {
+        let mut collector = Collector {
+            redactor: self,
+            values: Vec::new(),
+            redacted: false,
+        };
+        record(&mut collector);
+        collector.redacted.then_some(collector.values)
+    }
+}
+
+/// A recorded value, kept until the redacted copy is passed on.
+enum Owned {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Str(String),
+    Debug(DisplayValue<String>),
+}
+
+impl Owned {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Owned::Bool(value) => value,
+            Owned::I64(value) => value,
+            Owned::U64(value) => value,
+            Owned::I128(value) => value,
+            Owned::U128(value) => value,
+            Owned::F64(value) => value,
+            Owned::Str(value) => value,
+            Owned::Debug(value) => value,
+        }
+    }
+}
+
+struct Collector<'r> {
+    redactor: &'r Redactor,
+    values: Vec<(Field, Owned)>,
+    redacted: bool,
+}
+
+impl Collector<'_> {
+    fn push(&mut self, field: &Field, value: Owned) {
+        if self.redactor.redacts_field(field) {
+            self.redacted = true;
+            self.values
+                .push((field.clone(), Owned::Str(REDACTED.to_string())));
+        } else {
+            self.values.push((field.clone(), value));
+        }
+    }
+
+    fn push_text(&mut self, field: &Field, text: &str, debug: bool) {
+        let text = match self.redactor.redact_str(text) {
+            Cow::Borrowed(text) => text.to_string(),
+            Cow::Owned(text) => {
+                self.redacted = true;
+                text
+            }
+        };
+        let value = if debug {
+            Owned::Debug(field::display(text))
+        } else {
+            Owned::Str(text)
+        };
+        self.push(field, value);
+    }
+}
+
+impl Visit for Collector<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, Owned::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Owned::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Owned::U64(value));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.push(field, Owned::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.push(field, Owned::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, Owned::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push_text(field, value, false);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push_text(field, &format!("{:?}", value), true);
+    }
+}
+
+/// Calls `f` with `values` as a value set of `fields`.
+///
+/// Value sets are built from arrays, so more than [`MAX_FIELDS`] values are
+/// replaced by a single redacted `message`, or first field if there is none.
+fn with_value_set<R>(
+    fields: &FieldSet,
+    values: &[(Field, Owned)],
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    let redacted;
+    let values = if values.len() > MAX_FIELDS {
+        let (field, _) = values
+            .iter()
+            .find(|(field, _)| field.name() == "message")
+            .unwrap_or(&values[0]);
+        redacted = [(field.clone(), Owned::Str(REDACTED.to_string()))];
+        &redacted[..]
+    } else {
+        values
+    };
+    // unused entries are skipped
+    let padding = match values.first() {
+        Some((field, _)) => field,
+        None => {
+            let empty: [(&Field, Option<&dyn Value>); 0] = [];
+            return f(&fields.value_set(&empty));
+        }
+    };
+    let entries: [(&Field, Option<&dyn Value>); MAX_FIELDS] =
+        std::array::from_fn(|i| match values.get(i) {
+            Some((field, value)) => (field, Some(value.as_value())),
+            None => (padding, None),
+        });
+    f(&fields.value_set(&entries))
+}
+
+/// Layer passing redacted copies of spans and events to `inner`, see
+/// [`Redactor::layer`].
+pub struct RedactingLayer<L> {
+    inner: L,
+    redactor: Redactor,
+}
+
+impl<S, L> Layer<S> for RedactingLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(
+        &self,
+        metadata: &'static Metadata<'static>,
+    ) -> tracing::subscriber::Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if !self.redactor.inspects(metadata) {
+            return self.inner.on_new_span(attrs, id, ctx);
+        }
+        let values = match self.redactor.collect(|visitor| attrs.record(visitor)) {
+            Some(values) => values,
+            None => return self.inner.on_new_span(attrs, id, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let redacted = if attrs.is_root() {
+                span::Attributes::new_root(metadata, values)
+            } else if attrs.is_contextual() {
+                span::Attributes::new(metadata, values)
+            } else {
+                let parent = attrs.parent().cloned().expect("explicit parent");
+                span::Attributes::child_of(parent, metadata, values)
+            };
+            self.inner.on_new_span(&redacted, id, ctx);
+        });
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, span: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let metadata = match ctx.metadata(span) {
+            Some(metadata) if self.redactor.inspects(metadata) => metadata,
+            _ => return self.inner.on_record(span, values, ctx),
+        };
+        let redacted = match self.redactor.collect(|visitor| values.record(visitor)) {
+            Some(redacted) => redacted,
+            None => return self.inner.on_record(span, values, ctx),
+        };
+        with_value_set(metadata.fields(), &redacted, |values| {
+            self.inner.on_record(span, &span::Record::new(values), ctx);
+        });
+    }
+
+    fn on_follows_from(&self, span: &span::Id, follows: &span::Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !self.redactor.inspects(metadata) {
+            return self.inner.on_event(event, ctx);
+        }
+        let values = match self.redactor.collect(|visitor| event.record(visitor)) {
+            Some(values) => values,
+            None => return self.inner.on_event(event, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let redacted = if event.is_contextual() {
+                Event::new(metadata, values)
+            } else {
+                Event::new_child_of(event.parent().cloned(), metadata, values)
+            };
+            self.inner.on_event(&redacted, ctx);
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &span::Id, new: &span::Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx);
+    }
+
+    // lets e.g. `OpenTelemetrySpanExt::context` find the wrapped layer
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer, Registry};
+
+    use reqwest::StatusCode;
+
+    use super::{with_value_set, Owned, Redactor, MAX_FIELDS, REDACTED};
+    use crate::{configuration::RedactionSettings, relayer::error::RelayerError};
+
+    const KEY: &str = "6c6b7a1a0bd2f5dfc4a1ef4a3d05cd1e5e8a2d20e40f1a9c2e2d1b3a4f5e6d7c";
+
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for Recorded {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Recorded {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    fn with_hex_keys() -> Redactor {
+        Redactor::new(&RedactionSettings {
+            hex_keys: true,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn redacts_fields_and_keys() {
+        let recorded = Recorded::default();
+        let redactor = with_hex_keys();
+        let _guard = tracing::subscriber::set_default(
+            Registry::default().with(redactor.layer(recorded.clone())),
+        );
+
+        tracing::info_span!("call", secret_key = "123", pool = "0xabc").in_scope(|| {
+            tracing::info!(key = %format!("0x{}", KEY), "loaded {:?}", Some(KEY));
+        });
+
+        let recorded = recorded.0.lock().unwrap().clone();
+        let expected = [
+            ("secret_key", REDACTED.to_string()),
+            ("pool", "0xabc".to_string()),
+            ("message", format!("loaded Some(\"{}\")", REDACTED)),
+            ("key", format!("0x{}", REDACTED)),
+        ];
+        let expected = expected
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(recorded, expected);
+    }
+
+    #[test]
+    fn long_hex_runs_are_redacted() {
+        let redactor = with_hex_keys();
+        let signature = format!("{}{}", KEY, KEY);
+        assert_eq!(redactor.redact_str(&signature), REDACTED);
+        assert_eq!(
+            redactor.redact_str(&format!("key {}.", KEY)),
+            format!("key {}.", REDACTED)
+        );
+        let address = "0x0b1f3d6a1c7e9b2d4f6a8c0e1b3d5f7a9c2e4b6d";
+        assert_eq!(redactor.redact_str(address), address);
+
+        let redactor = Redactor::new(&RedactionSettings::default());
+        assert_eq!(redactor.redact_str(&signature), signature);
+    }
+
+    #[test]
+    fn redacts_logged_errors() {
+        let recorded = Recorded::default();
+        let _guard = tracing::subscriber::set_default(
+            Registry::default().with(with_hex_keys().layer(recorded.clone())),
+        );
+
+        let err = RelayerError::service_error(
+            StatusCode::BAD_REQUEST,
+            &format!("invalid deposit signature 0x{}{}", KEY, KEY),
+        );
+        tracing::warn!(error = ?err, "failed to send transactions");
+
+        let recorded = recorded.0.lock().unwrap().clone();
+        let (_, error) = recorded.iter().find(|(field, _)| field == "error").unwrap();
+        assert_eq!(
+            error,
+            &format!(
+                "ServiceError(400, \"invalid deposit signature 0x{}\")",
+                REDACTED
+            )
+        );
+    }
+
+    #[test]
+    fn unconfigured_fields_pass_through() {
+        let recorded = Recorded::default();
+        let redactor = Redactor::new(&RedactionSettings::default());
+        let _guard = tracing::subscriber::set_default(
+            Registry::default().with(redactor.layer(recorded.clone())),
+        );
+
+        tracing::info!(root = KEY, "synced");
+
+        let recorded = recorded.0.lock().unwrap().clone();
+        assert_eq!(recorded[1], ("root".to_string(), KEY.to_string()));
+    }
+
+    #[test]
+    fn value_sets_stay_within_bounds() {
+        let _guard = tracing::subscriber::set_default(Registry::default());
+        let span = tracing::info_span!("call", secret_key = "123");
+        let fields = span.metadata().unwrap().fields();
+        let field = fields.field("secret_key").unwrap();
+
+        assert!(with_value_set(fields, &[], |values| values.is_empty()));
+
+        let values = (0..=MAX_FIELDS)
+            .map(|_| (field.clone(), Owned::Str("123".to_string())))
+            .collect::<Vec<_>>();
+        let recorded = Recorded::default();
+        with_value_set(fields, &values, |values| {
+            values.record(&mut recorded.clone())
+        });
+        let recorded = recorded.0.lock().unwrap().clone();
+        assert_eq!(recorded, [("secret_key".to_string(), REDACTED.to_string())]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+use opentelemetry::{
+    sdk::{
+        trace::{self, Sampler, ShouldSample},
+        InstrumentationLibrary, Resource,
+    },
+    trace::{
+        Link, OrderMap, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId,
+        TraceState,
+    },
+    Context, Key, KeyValue, Value,
+};
+
+use crate::configuration::{SamplerKind, SamplingSettings};
+
+/// Trace config exporting `resource` and sampling traces per `settings`.
+pub fn trace_config(resource: Vec<KeyValue>, settings: &SamplingSettings) -> trace::Config {
+    let config = trace::config().with_resource(Resource::new(resource));
+    match settings.sampler {
+        SamplerKind::Always => with_sampler(config, Sampler::AlwaysOn, settings.parent_based),
+        SamplerKind::Never => with_sampler(config, Sampler::AlwaysOff, settings.parent_based),
+        SamplerKind::Ratio { ratio } => with_sampler(
+            config,
+            Sampler::TraceIdRatioBased(ratio),
+            settings.parent_based,
+        ),
+        SamplerKind::RateLimited { traces_per_second } => with_sampler(
+            config,
+            RateLimitingSampler::new(traces_per_second),
+            settings.parent_based,
+        ),
+    }
+}
+
+fn with_sampler<S: ShouldSample + 'static>(
+    config: trace::Config,
+    sampler: S,
+    parent_based: bool,
+) -> trace::Config {
+    if parent_based {
+        config.with_sampler(Sampler::ParentBased(Box::new(sampler)))
+    } else {
+        config.with_sampler(sampler)
+    }
+}
+
+/// Samples up to `traces_per_second` traces, allowing bursts of as many
+/// traces (at least one) after idle periods.
+#[derive(Clone, Debug)]
+pub struct RateLimitingSampler {
+    traces_per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    credits: f64,
+    updated: Instant,
+}
+
+impl RateLimitingSampler {
+    pub fn new(traces_per_second: f64) -> Self {
+        Self {
+            traces_per_second,
+            bucket: Arc::new(Mutex::new(Bucket {
+                credits: max_credits(traces_per_second),
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    fn try_take(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.credits = (bucket.credits + elapsed * self.traces_per_second)
+            .min(max_credits(self.traces_per_second));
+        bucket.updated = now;
+        if bucket.credits >= 1.0 {
+            bucket.credits -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn max_credits(traces_per_second: f64) -> f64 {
+    traces_per_second.max(1.0)
+}
+
+impl ShouldSample for RateLimitingSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &OrderMap<Key, Value>,
+        _links: &[Link],
+        _instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        let decision = if self.try_take() {
+            SamplingDecision::RecordAndSample
+        } else {
+            SamplingDecision::Drop
+        };
+        SamplingResult {
+            decision,
+            attributes: Vec::new(),
+            trace_state: match parent_context {
+                Some(cx) => cx.span().span_context().trace_state().clone(),
+                None => TraceState::default(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{
+        sdk::{trace::ShouldSample, InstrumentationLibrary},
+        trace::{OrderMap, SamplingDecision, SpanKind, TraceId},
+    };
+
+    use super::RateLimitingSampler;
+
+    #[test]
+    fn traces_are_rate_limited() {
+        let sampler = RateLimitingSampler::new(2.0);
+        let library = InstrumentationLibrary::new("test", None, None);
+        let sampled = (0..10)
+            .filter(|_| {
+                let result = sampler.should_sample(
+                    None,
+                    TraceId::from_bytes([1; 16]),
+                    "span",
+                    &SpanKind::Internal,
+                    &OrderMap::default(),
+                    &[],
+                    &library,
+                );
+                result.decision == SamplingDecision::RecordAndSample
+            })
+            .count();
+        assert_eq!(sampled, 2);
+    }
+}
@@ -0,0 +1,139 @@
+//! Spans of client calls.
+//!
+//! [`traced!`] wraps the RPC and HTTP client methods in spans carrying the
+//! called endpoint and the outcome of the call.
+use std::future::Future;
+
+use tracing::Span;
+
+/// Defines client methods that each run in a span with the shared `fields`
+/// and an `outcome` field recording whether the call failed.
+///
+/// Every method must be an `async fn` taking `&self` and returning a
+/// `Result`. Its `#[span(...)]` attribute takes the span `name` and
+/// optionally `skip` and `fields` in addition to the shared ones, as in
+/// `#[tracing::instrument]`:
+///
+/// ```ignore
+/// traced! {
+///     fields(endpoint = %self.endpoint);
+///
+///     /// Fetches the relayer info.
+///     #[span(name = "relayer.info")]
+///     pub async fn info(&self) -> Result<InfoResponse, RelayerError> {
+///         self.get("info", "").await
+///     }
+/// }
+/// ```
+macro_rules! traced {
+    (fields($($common:tt)*);) => {};
+    (
+        fields($($common:tt)*);
+
+        $(#[doc = $doc:literal])*
+        #[span(
+            name = $name:literal
+            $(, skip($($skip:ident),+))?
+            $(, fields($($field:tt)+))?
+            $(,)?
+        )]
+        $vis:vis async fn $method:ident($($params:tt)*) -> $ret:ty $body:block
+
+        $($rest:tt)*
+    ) => {
+        $(#[doc = $doc])*
+        #[tracing::instrument(
+            name = $name,
+            skip(self $($(, $skip)+)?),
+            fields($($common)* $(, $($field)+)?, outcome = tracing::field::Empty),
+            err(Debug),
+        )]
+        $vis async fn $method($($params)*) -> $ret {
+            $crate::telemetry::span::record_outcome(async move $body).await
+        }
+
+        $crate::telemetry::span::traced! { fields($($common)*); $($rest)* }
+    };
+}
+
+pub(crate) use traced;
+
+/// Awaits `call` and records its `outcome`, `ok` or `error`, on the current span.
+pub(crate) async fn record_outcome<R, E>(call: impl Future<Output = Result<R, E>>) -> Result<R, E> {
+    let result = call.await;
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    Span::current().record("outcome", outcome);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Client {
+        endpoint: &'static str,
+    }
+
+    impl Client {
+        traced! {
+            fields(endpoint = %self.endpoint);
+
+            #[span(name = "client.get")]
+            async fn get(&self, value: u32) -> Result<u32, String> {
+                tracing::info!("getting");
+                Ok(value)
+            }
+
+            #[span(name = "client.fail", skip(reason), fields(reason = %reason))]
+            async fn fail(&self, reason: &str) -> Result<u32, String> {
+                tracing::info!("failing");
+                Err(reason.to_string())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn outcome_is_recorded() {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = Client {
+            endpoint: "http://localhost",
+        };
+        assert_eq!(client.get(1).await, Ok(1));
+        assert_eq!(client.fail("boom").await, Err("boom".to_string()));
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let closed = output
+            .lines()
+            .filter(|line| line.contains("close"))
+            .collect::<Vec<_>>();
+        assert_eq!(closed.len(), 2, "{}", output);
+        assert!(closed[0].contains("client.get{value=1 endpoint=http://localhost outcome=\"ok\"}"));
+        assert!(closed[1]
+            .contains("client.fail{endpoint=http://localhost reason=boom outcome=\"error\"}"));
+    }
+}
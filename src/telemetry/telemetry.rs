@@ -1,64 +1,581 @@
-use crate::configuration::{self, TelemetrySettings};
-use opentelemetry::global;
+use super::{
+    error::TelemetryError,
+    file::{self, RollingFile, WriterGuard},
+    otlp,
+    redact::Redactor,
+    sampler,
+};
+use crate::configuration::{
+    LogLevel, OutputKind, Propagator, RedactionSettings, SamplingSettings, TelemetryOutput,
+    TelemetrySettings, Version,
+};
+use opentelemetry::{
+    global,
+    propagation::TextMapPropagator,
+    sdk::{
+        propagation::{TextMapCompositePropagator, TraceContextPropagator},
+        trace,
+    },
+    trace::TraceError,
+    KeyValue,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, PoisonError,
+};
+use tokio::sync::watch;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::prelude::*;
-use tracing_subscriber::{EnvFilter, Registry};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{filter::ParseError, reload, EnvFilter, Layer, Registry};
 
-pub fn init_stdout(name: String, env_filter: String) {
-    let env_filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+pub(super) type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Filter following the global directives, with the log level of its output
+/// if the output has its own.
+pub(super) type FollowedFilter = (Option<LogLevel>, FilterHandle);
+
+/// Handles to the filters that follow the global log level and directives.
+static LOG_FILTERS: Mutex<Vec<FollowedFilter>> = Mutex::new(Vec::new());
+
+/// Directives kept by [`set_log_level`], see [`TelemetrySettings::log_directives`].
+static LOG_DIRECTIVES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Set once an `init_*` call has installed its subscriber.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps exported spans and log files flowing; pending spans and lines are
+/// flushed when the guard is dropped or [`TelemetryGuard::shutdown`] is called.
+///
+/// Flushing blocks until the exporter is done, so with a current-thread Tokio
+/// runtime drop the guard after the runtime has stopped.
+#[must_use = "exported spans are flushed and the exporter is stopped when the guard is dropped"]
+#[derive(Debug)]
+pub struct TelemetryGuard {
+    exporter: bool,
+    writers: Vec<WriterGuard>,
+}
+
+impl TelemetryGuard {
+    pub(super) fn new(exporter: bool) -> Self {
+        Self {
+            exporter,
+            writers: Vec::new(),
+        }
+    }
+
+    /// Flushes pending spans and log lines and stops the exporter.
+    pub fn shutdown(mut self) {
+        self.flush_outputs();
+    }
+
+    fn flush_outputs(&mut self) {
+        if std::mem::take(&mut self.exporter) {
+            global::shutdown_tracer_provider();
+        }
+        self.writers.clear();
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        self.flush_outputs();
+    }
+}
+
+/// Fails if telemetry or another global subscriber is already installed,
+/// before anything global (e.g. the tracer provider of a running exporter) is replaced.
+pub(super) fn ensure_uninitialized() -> Result<(), TelemetryError> {
+    if INITIALIZED.load(Ordering::SeqCst) || tracing::dispatcher::has_been_set() {
+        return Err(TelemetryError::AlreadyInitialized);
+    }
+    Ok(())
+}
+
+/// Installs `subscriber` globally and remembers its `filters` for [`set_log_filter`].
+pub(super) fn try_init<S: SubscriberInitExt>(
+    subscriber: S,
+    filters: Vec<FollowedFilter>,
+    directives: &[String],
+) -> Result<(), TelemetryError> {
+    subscriber.try_init()?;
+    INITIALIZED.store(true, Ordering::SeqCst);
+    *LOG_FILTERS.lock().unwrap_or_else(PoisonError::into_inner) = filters;
+    *LOG_DIRECTIVES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = directives.to_vec();
+    Ok(())
+}
+
+/// Filter from `RUST_LOG` if set, `default` otherwise. Only the latter
+/// follows [`set_log_filter`], so `RUST_LOG` always wins.
+pub(super) fn default_env_filter(
+    default: impl FnOnce() -> Result<EnvFilter, ParseError>,
+) -> Result<(reload::Layer<EnvFilter, Registry>, Vec<FollowedFilter>), ParseError> {
+    let (env_filter, followed) = match EnvFilter::try_from_default_env() {
+        Ok(env_filter) => (env_filter, false),
+        Err(_) => (default()?, true),
+    };
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let filters = if followed {
+        vec![(None, handle)]
+    } else {
+        Vec::new()
+    };
+    Ok((env_filter, filters))
+}
+
+/// Propagator handling the headers of all `propagators`.
+pub fn propagator(propagators: &[Propagator]) -> TextMapCompositePropagator {
+    let propagators = propagators
+        .iter()
+        .map(|propagator| -> Box<dyn TextMapPropagator + Send + Sync> {
+            match propagator {
+                Propagator::TraceContext => Box::new(TraceContextPropagator::new()),
+                Propagator::Jaeger => Box::new(opentelemetry_jaeger::Propagator::new()),
+            }
+        })
+        .collect();
+    TextMapCompositePropagator::new(propagators)
+}
+
+/// Filter passing events at `log_level` and above, except for targets with
+/// their own level in `directives`.
+pub fn log_filter(log_level: LogLevel, directives: &[String]) -> Result<EnvFilter, ParseError> {
+    let mut filter = EnvFilter::try_new(log_level.to_string())?;
+    for directive in directives {
+        filter = filter.add_directive(directive.parse()?);
+    }
+    Ok(filter)
+}
+
+/// Replaces the default level of the installed subscriber, keeping its
+/// directives, see [`set_log_filter`].
+pub fn set_log_level(log_level: LogLevel) -> Result<(), String> {
+    let directives = LOG_DIRECTIVES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    set_log_filter(log_level, &directives)
+}
+
+/// Replaces the default level and the directives of the installed subscriber.
+/// Outputs with their own log level keep it, outputs filtered by `RUST_LOG`
+/// are left alone.
+pub fn set_log_filter(log_level: LogLevel, directives: &[String]) -> Result<(), String> {
+    if !INITIALIZED.load(Ordering::SeqCst) {
+        return Err("telemetry is not initialized".to_string());
+    }
+    let filters = LOG_FILTERS.lock().unwrap_or_else(PoisonError::into_inner);
+    // parse all filters first so that invalid directives change nothing
+    let env_filters = filters
+        .iter()
+        .map(|(own_level, _)| log_filter(own_level.unwrap_or(log_level), directives))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())?;
+    for ((_, handle), env_filter) in filters.iter().zip(env_filters) {
+        handle.reload(env_filter).map_err(|err| err.to_string())?;
+    }
+    *LOG_DIRECTIVES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = directives.to_vec();
+    Ok(())
+}
+
+/// Applies the log level and directives of every settings update, e.g. from
+/// [`crate::configuration::ConfigLoader::watch`], until the sender is dropped.
+pub fn follow_log_filter<S, F>(mut settings: watch::Receiver<S>, telemetry: F)
+where
+    S: Send + Sync + 'static,
+    F: Fn(&S) -> &TelemetrySettings + Send + 'static,
+{
+    let filter = move |settings: &S| {
+        let telemetry = telemetry(settings);
+        (telemetry.log_level, telemetry.log_directives.clone())
+    };
+    let mut current = filter(&settings.borrow());
+    tokio::spawn(async move {
+        while settings.changed().await.is_ok() {
+            let new = filter(&settings.borrow());
+            if new == current {
+                continue;
+            }
+            match set_log_filter(new.0, &new.1) {
+                Ok(()) => {
+                    tracing::info!(
+                        "log filter changed from {} {:?} to {} {:?}",
+                        current.0,
+                        current.1,
+                        new.0,
+                        new.1
+                    );
+                    current = new;
+                }
+                Err(err) => tracing::warn!("failed to change log filter: {}", err),
+            }
+        }
+    });
+}
+
+pub fn init_stdout(name: String, env_filter: String) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+    let (env_filter, filters) = default_env_filter(|| Ok(EnvFilter::new(env_filter)))?;
 
     let formatting_layer = BunyanFormattingLayer::new(name, std::io::stdout)
-        .skip_fields(vec!["file", "log.file"].into_iter())
-        .expect("One of the specified fields cannot be skipped");
-    Registry::default()
+        .skip_fields(vec!["file", "log.file"].into_iter())?;
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(formatting_layer)
-        .with(JsonStorageLayer)
-        .init();
+        .with(JsonStorageLayer);
+    try_init(subscriber, filters, &[])?;
+    Ok(TelemetryGuard::new(false))
+}
+
+pub fn init_pretty(env_filter: String) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+    let (env_filter, filters) = default_env_filter(|| Ok(EnvFilter::new(env_filter)))?;
+
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer());
+    try_init(subscriber, filters, &[])?;
+    Ok(TelemetryGuard::new(false))
 }
 
-pub fn init_sink(name: String, env_filter: String) {
-    let env_filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+pub fn init_sink(name: String, env_filter: String) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+    let (env_filter, filters) = default_env_filter(|| Ok(EnvFilter::new(env_filter)))?;
 
     let formatting_layer = BunyanFormattingLayer::new(name, std::io::sink);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(formatting_layer)
-        .with(JsonStorageLayer)
-        .init();
+        .with(JsonStorageLayer);
+    try_init(subscriber, filters, &[])?;
+    Ok(TelemetryGuard::new(false))
 }
 
 use opentelemetry::runtime::Tokio;
 
-pub fn init_jaeger(name: String, log_level: String, endpoint: &Option<String>) {
-    global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
+pub fn init_jaeger(
+    name: String,
+    log_level: String,
+    endpoint: &Option<String>,
+) -> Result<TelemetryGuard, TelemetryError> {
+    init_jaeger_with_resource(
+        name,
+        log_level,
+        endpoint,
+        Vec::new(),
+        &SamplingSettings::default(),
+    )
+}
+
+/// Same as [`init_jaeger`], attaching `resource` attributes to every exported span
+/// and sampling traces per `sampling`.
+pub fn init_jaeger_with_resource(
+    name: String,
+    log_level: String,
+    endpoint: &Option<String>,
+    resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+    let tracer = jaeger_tracer(name, endpoint, resource, sampling)?;
+    let guard = TelemetryGuard::new(true);
+
+    let (env_filter, filter) = reload::Layer::new(EnvFilter::new(log_level));
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer().with_target(false))
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    try_init(subscriber, vec![(None, filter)], &[])?;
+    global::set_text_map_propagator(propagator(&[Propagator::TraceContext, Propagator::Jaeger]));
+    Ok(guard)
+}
 
-    let mut agent_pipeline = opentelemetry_jaeger::new_agent_pipeline().with_service_name(name);
+/// Installs a batch Jaeger span exporter and returns its tracer.
+fn jaeger_tracer(
+    name: String,
+    endpoint: &Option<String>,
+    resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+) -> Result<trace::Tracer, TraceError> {
+    let mut agent_pipeline = opentelemetry_jaeger::new_agent_pipeline()
+        .with_service_name(name)
+        .with_trace_config(sampler::trace_config(resource, sampling));
 
     if let Some(agent_endpoint) = endpoint {
         agent_pipeline = agent_pipeline.with_endpoint(agent_endpoint);
     }
 
-    let tracer = agent_pipeline.install_batch(Tokio).unwrap();
+    agent_pipeline.install_batch(Tokio)
+}
 
-    Registry::default()
-        .with(tracing_subscriber::EnvFilter::new(log_level))
-        .with(tracing_subscriber::fmt::layer().with_target(false))
-        .with(tracing_opentelemetry::layer().with_tracer(tracer))
-        .init();
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Installs a subscriber writing to all `outputs`, each filtered by its own
+/// log level or `log_level`, and by `directives`. `RUST_LOG` overrides both
+/// `log_level` and `directives` if set. Exported traces are sampled per `sampling`,
+/// values are redacted per `redaction` before reaching any output. Trace context
+/// is exchanged with other services in the formats of `propagation`.
+#[allow(clippy::too_many_arguments)]
+pub fn init_outputs(
+    name: String,
+    log_level: LogLevel,
+    directives: &[String],
+    outputs: &[TelemetryOutput],
+    resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+    redaction: &RedactionSettings,
+    propagation: &[Propagator],
+) -> Result<TelemetryGuard, TelemetryError> {
+    ensure_uninitialized()?;
+    let (layers, filters, guard) =
+        output_layers(name, log_level, directives, outputs, resource, sampling)?;
+
+    let redactor = Redactor::new(redaction);
+    let layer = if redactor.is_noop() {
+        layers.boxed()
+    } else {
+        redactor.layer(layers).boxed()
+    };
+    try_init(Registry::default().with(layer), filters, directives)?;
+    global::set_text_map_propagator(propagator(propagation));
+    Ok(guard)
 }
 
-pub fn setup(telemetry_settings: &TelemetrySettings) {
-    let log_level = telemetry_settings.log_level.to_string();
-    let name = telemetry_settings.clone().service_name;
+/// Layers writing to `outputs`, see [`init_outputs`], with the handles of
+/// the filters following the global log level and directives.
+fn output_layers(
+    name: String,
+    log_level: LogLevel,
+    directives: &[String],
+    outputs: &[TelemetryOutput],
+    resource: Vec<KeyValue>,
+    sampling: &SamplingSettings,
+) -> Result<(Vec<BoxedLayer>, Vec<FollowedFilter>, TelemetryGuard), TelemetryError> {
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    let mut filters = Vec::new();
+    let mut writers = Vec::new();
+    let mut exporter = false;
+    let mut bunyan = false;
+    for output in outputs {
+        let (env_filter, followed) = match output.log_level {
+            Some(own_level) => {
+                let (env_filter, handle) = reload::Layer::new(log_filter(own_level, directives)?);
+                (env_filter, vec![(Some(own_level), handle)])
+            }
+            None => default_env_filter(|| log_filter(log_level, directives))?,
+        };
+        filters.extend(followed);
 
-    match telemetry_settings.kind {
-        configuration::TelemetryKind::Stdout => init_stdout(name, log_level),
-        configuration::TelemetryKind::Jaeger => {
-            init_jaeger(name, log_level, &telemetry_settings.endpoint)
-        }
+        let layer: BoxedLayer = match &output.kind {
+            OutputKind::Bunyan => {
+                bunyan = true;
+                BunyanFormattingLayer::new(name.clone(), std::io::stdout)
+                    .skip_fields(vec!["file", "log.file"].into_iter())?
+                    .with_filter(env_filter)
+                    .boxed()
+            }
+            OutputKind::Pretty => tracing_subscriber::fmt::layer()
+                .with_filter(env_filter)
+                .boxed(),
+            OutputKind::File {
+                path,
+                rotation,
+                max_size_mb,
+                max_files,
+            } => {
+                bunyan = true;
+                let max_size = max_size_mb.map(|mb| mb * 1024 * 1024);
+                let file = RollingFile::open(path, *rotation, max_size, *max_files)?;
+                let (writer, guard) = file::non_blocking(file, file::DEFAULT_BUFFERED_LINES)?;
+                writers.push(guard);
+                BunyanFormattingLayer::new(name.clone(), writer)
+                    .skip_fields(vec!["file", "log.file"].into_iter())?
+                    .with_filter(env_filter)
+                    .boxed()
+            }
+            OutputKind::Sink => {
+                bunyan = true;
+                BunyanFormattingLayer::new(name.clone(), std::io::sink)
+                    .with_filter(env_filter)
+                    .boxed()
+            }
+            OutputKind::Jaeger { endpoint } => {
+                let tracer = jaeger_tracer(name.clone(), endpoint, resource.clone(), sampling)?;
+                exporter = true;
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(env_filter)
+                    .boxed()
+            }
+            OutputKind::Otlp { endpoint, settings } => {
+                let tracer =
+                    otlp::tracer(name.clone(), endpoint, settings, resource.clone(), sampling)?;
+                exporter = true;
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(env_filter)
+                    .boxed()
+            }
+        };
+        layers.push(layer);
+    }
+    if bunyan {
+        // span fields for the Bunyan formatters, regardless of their filters
+        layers.insert(0, JsonStorageLayer.boxed());
+    }
+    Ok((layers, filters, TelemetryGuard { exporter, writers }))
+}
+
+/// Installs the outputs of `telemetry_settings`, see [`TelemetrySettings::resolved_outputs`].
+///
+/// Keep the returned guard alive until the service exits, dropping it flushes
+/// pending spans. Fails if telemetry is already initialized.
+pub fn setup(telemetry_settings: &TelemetrySettings) -> Result<TelemetryGuard, TelemetryError> {
+    setup_with_version(telemetry_settings, &Version::default())
+}
+
+/// Same as [`setup`], reporting `version` as resource attributes of exported traces.
+pub fn setup_with_version(
+    telemetry_settings: &TelemetrySettings,
+    version: &Version,
+) -> Result<TelemetryGuard, TelemetryError> {
+    init_outputs(
+        telemetry_settings.service_name.clone(),
+        telemetry_settings.log_level,
+        &telemetry_settings.log_directives,
+        &telemetry_settings.resolved_outputs(),
+        resource(telemetry_settings, version),
+        &telemetry_settings.sampling,
+        &telemetry_settings.redaction,
+        &telemetry_settings.propagators(),
+    )
+}
+
+fn resource(telemetry_settings: &TelemetrySettings, version: &Version) -> Vec<KeyValue> {
+    let mut resource = version.resource_attributes();
+    resource.push(KeyValue::new(
+        "deployment.environment",
+        telemetry_settings.environment.to_string(),
+    ));
+    resource.extend(
+        telemetry_settings
+            .resource_attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+    );
+    resource
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+        Context,
+    };
+
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::{log_filter, output_layers, propagator, resource};
+    use crate::configuration::{
+        Environment, LogLevel, OutputKind, Propagator, TelemetryOutput, TelemetrySettings, Version,
+    };
+
+    #[test]
+    fn propagators_inject_configured_headers() {
+        let context = Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_bytes([1; 16]),
+            SpanId::from_bytes([2; 8]),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+        let injected = |propagators: &[Propagator]| {
+            let mut headers = HashMap::new();
+            propagator(propagators).inject_context(&context, &mut headers);
+            (
+                headers.contains_key("traceparent"),
+                headers.contains_key("uber-trace-id"),
+            )
+        };
+
+        assert_eq!(injected(&[Propagator::TraceContext]), (true, false));
+        assert_eq!(
+            injected(&[Propagator::TraceContext, Propagator::Jaeger]),
+            (true, true)
+        );
+        assert_eq!(injected(&[Propagator::Jaeger]), (false, true));
+    }
+
+    #[test]
+    fn resource_reports_settings_environment() {
+        let mut settings: TelemetrySettings =
+            serde_json::from_str(r#"{"service_name": "test", "log_level": "INFO"}"#).unwrap();
+        settings.environment = Environment::Staging;
+        let environment = resource(&settings, &Version::default())
+            .into_iter()
+            .find(|attribute| attribute.key.as_str() == "deployment.environment")
+            .unwrap();
+        assert_eq!(environment.value.as_str(), "staging");
+    }
+
+    #[test]
+    fn directives_override_log_level() {
+        let filter = log_filter(LogLevel::WARN, &["noisy=debug".to_string()]).unwrap();
+        tracing::subscriber::with_default(Registry::default().with(filter), || {
+            assert!(tracing::info_span!("info").is_disabled());
+            assert!(!tracing::warn_span!("warn").is_disabled());
+            assert!(!tracing::debug_span!(target: "noisy", "debug").is_disabled());
+            assert!(tracing::trace_span!(target: "noisy", "trace").is_disabled());
+        });
+        assert!(log_filter(LogLevel::INFO, &["noisy=loud".to_string()]).is_err());
+    }
+
+    #[test]
+    fn outputs_have_own_filters() {
+        let dir = std::env::temp_dir().join(format!("zkbob-outputs-{}", std::process::id()));
+        let file = |name: &str, log_level| TelemetryOutput {
+            kind: OutputKind::File {
+                path: dir.join(name),
+                rotation: Default::default(),
+                max_size_mb: None,
+                max_files: None,
+            },
+            log_level,
+        };
+        let outputs = [
+            file("warn.log", Some(LogLevel::WARN)),
+            file("info.log", None),
+        ];
+        let (layers, filters, guard) = output_layers(
+            "test".to_string(),
+            LogLevel::INFO,
+            &["noisy=error".to_string()],
+            &outputs,
+            Vec::new(),
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(filters.len(), 2);
+
+        tracing::subscriber::with_default(Registry::default().with(layers), || {
+            tracing::info!("info line");
+            tracing::warn!("warn line");
+            tracing::warn!(target: "noisy", "noisy line");
+        });
+        guard.shutdown();
+
+        let read = |name| fs::read_to_string(dir.join(name)).unwrap();
+        let (warn, info) = (read("warn.log"), read("info.log"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!warn.contains("info line") && warn.contains("warn line"));
+        assert!(info.contains("info line") && info.contains("warn line"));
+        assert!(!warn.contains("noisy line") && !info.contains("noisy line"));
     }
 }
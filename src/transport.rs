@@ -0,0 +1,311 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use jsonrpc_core::{Call, Value};
+use tokio::sync::oneshot;
+use web3::{error::TransportError, BatchTransport, Error as Web3Error, RequestId, Transport};
+
+/// Upper bounds of the batch size histogram buckets.
+pub const BATCH_SIZE_BUCKETS: [u64; 8] = [1, 2, 5, 10, 20, 50, 100, u64::MAX];
+
+type PendingRequest = (RequestId, Call, oneshot::Sender<web3::Result<Value>>);
+
+#[derive(Debug, Default)]
+struct Pending {
+    requests: Vec<PendingRequest>,
+    flush_scheduled: bool,
+}
+
+/// Counters describing how requests were coalesced.
+#[derive(Debug, Default)]
+pub struct BatchStats {
+    batches: AtomicU64,
+    requests: AtomicU64,
+    max_batch_size: AtomicU64,
+    buckets: [AtomicU64; BATCH_SIZE_BUCKETS.len()],
+}
+
+impl BatchStats {
+    /// Number of JSON-RPC batches (or single requests) sent to the inner transport.
+    pub fn batches(&self) -> u64 {
+        self.batches.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests issued through the transport.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn max_batch_size(&self) -> u64 {
+        self.max_batch_size.load(Ordering::Relaxed)
+    }
+
+    /// Non-cumulative count of batches per bucket of [`BATCH_SIZE_BUCKETS`].
+    pub fn batch_size_histogram(&self) -> Vec<(u64, u64)> {
+        BATCH_SIZE_BUCKETS
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(bound, count)| (*bound, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn observe(&self, size: usize) {
+        let size = size as u64;
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.requests.fetch_add(size, Ordering::Relaxed);
+        self.max_batch_size.fetch_max(size, Ordering::Relaxed);
+        if let Some(bucket) = BATCH_SIZE_BUCKETS.iter().position(|bound| size <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Transport that coalesces requests issued within `window` into one JSON-RPC batch.
+///
+/// Requests are buffered until the window elapses or `max_batch_size` requests are
+/// queued, whichever happens first. A lone request is sent as a regular call.
+/// Must be used from within a Tokio runtime.
+#[derive(Debug, Clone)]
+pub struct BatchingTransport<T> {
+    inner: T,
+    window: Duration,
+    max_batch_size: usize,
+    pending: Arc<Mutex<Pending>>,
+    stats: Arc<BatchStats>,
+}
+
+impl<T> BatchingTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    pub fn new(inner: T, window: Duration, max_batch_size: usize) -> Self {
+        Self {
+            inner,
+            window,
+            max_batch_size: max_batch_size.max(1),
+            pending: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<BatchStats> {
+        self.stats.clone()
+    }
+
+    fn take_pending(&self) -> Vec<PendingRequest> {
+        let mut pending = self.pending.lock().expect("batch queue lock is poisoned");
+        pending.flush_scheduled = false;
+        mem::take(&mut pending.requests)
+    }
+
+    fn dispatch(&self, requests: Vec<PendingRequest>) {
+        if requests.is_empty() {
+            return;
+        }
+        self.stats.observe(requests.len());
+
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            if requests.len() == 1 {
+                let (id, call, sender) = requests.into_iter().next().unwrap();
+                let _ = sender.send(inner.send(id, call).await);
+                return;
+            }
+
+            let (calls, senders): (Vec<_>, Vec<_>) = requests
+                .into_iter()
+                .map(|(id, call, sender)| ((id, call), sender))
+                .unzip();
+            match inner.send_batch(calls).await {
+                Ok(results) => {
+                    for (sender, result) in senders.into_iter().zip(results) {
+                        let _ = sender.send(result);
+                    }
+                }
+                Err(err) => {
+                    let message = err.to_string();
+                    for sender in senders {
+                        let _ = sender.send(Err(Web3Error::Transport(TransportError::Message(
+                            message.clone(),
+                        ))));
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<T> Transport for BatchingTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let (sender, receiver) = oneshot::channel();
+
+        let (flush_now, schedule_flush) = {
+            let mut pending = self.pending.lock().expect("batch queue lock is poisoned");
+            pending.requests.push((id, request, sender));
+            let flush_now = pending.requests.len() >= self.max_batch_size;
+            let schedule_flush = !flush_now && !pending.flush_scheduled;
+            if schedule_flush {
+                pending.flush_scheduled = true;
+            }
+            (flush_now, schedule_flush)
+        };
+
+        if flush_now {
+            self.dispatch(self.take_pending());
+        } else if schedule_flush {
+            let transport = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(transport.window).await;
+                transport.dispatch(transport.take_pending());
+            });
+        }
+
+        Box::pin(async move { receiver.await.map_err(|_| Web3Error::Internal)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use serde_json::{json, Value};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use web3::{transports::Http, Transport, Web3};
+
+    use super::BatchingTransport;
+
+    /// Minimal JSON-RPC server answering every call with its first parameter, or
+    /// the request id if it has none. Batches are answered in reversed order.
+    async fn stub_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let http_requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = http_requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(stream, counter.clone()));
+            }
+        });
+        (url, http_requests)
+    }
+
+    async fn serve(mut stream: TcpStream, http_requests: Arc<AtomicUsize>) {
+        let mut buf = Vec::new();
+        loop {
+            let header_end = loop {
+                if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+                let mut chunk = [0; 4096];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            };
+            let headers = String::from_utf8_lossy(&buf[..header_end]).to_lowercase();
+            let content_length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|len| len.trim().parse().unwrap())
+                .unwrap_or(0);
+            while buf.len() < header_end + content_length {
+                let mut chunk = [0; 4096];
+                let n = stream.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let body: Value =
+                serde_json::from_slice(&buf[header_end..header_end + content_length]).unwrap();
+            buf.drain(..header_end + content_length);
+            http_requests.fetch_add(1, Ordering::SeqCst);
+
+            let respond = |call: &Value| {
+                let result = match call["params"].get(0) {
+                    Some(param) => param.clone(),
+                    None => json!(format!("{:#x}", call["id"].as_u64().unwrap())),
+                };
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "result": result,
+                })
+            };
+            let response = match &body {
+                Value::Array(calls) => Value::Array(calls.iter().rev().map(respond).collect()),
+                call => respond(call),
+            }
+            .to_string();
+
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            stream.write_all(reply.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_are_batched() {
+        let (url, http_requests) = stub_server().await;
+        let transport =
+            BatchingTransport::new(Http::new(&url).unwrap(), Duration::from_millis(20), 100);
+        let stats = transport.stats();
+
+        let results =
+            futures::future::join_all((0..5).map(|i| transport.execute("echo", vec![json!(i)])))
+                .await;
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result.unwrap(), json!(i));
+        }
+
+        assert_eq!(http_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(stats.batches(), 1);
+        assert_eq!(stats.requests(), 5);
+        assert_eq!(stats.max_batch_size(), 5);
+    }
+
+    #[tokio::test]
+    async fn batch_is_flushed_when_full() {
+        let (url, http_requests) = stub_server().await;
+        let transport =
+            BatchingTransport::new(Http::new(&url).unwrap(), Duration::from_secs(60), 2);
+        let stats = transport.stats();
+        let web3 = Web3::new(transport);
+
+        let (a, b) = tokio::join!(web3.eth().block_number(), web3.eth().block_number());
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(http_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(stats.batch_size_histogram()[1], (2, 1));
+    }
+}
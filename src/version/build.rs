@@ -0,0 +1,87 @@
+//! Build script side of [`super`], enabled by the `build-info` feature.
+
+use std::{env, process::Command};
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::{BUILD_TIMESTAMP_VAR, COMMIT_HASH_VAR, FEATURES_VAR, REF_NAME_VAR, RUSTC_VERSION_VAR};
+
+/// Collects build metadata and passes it to the compiler. Must be called from a build script.
+///
+/// The git ref and commit can be overridden with `GIT_REF_NAME` and `GIT_COMMIT_HASH`,
+/// e.g. for CI builds from a source archive without `.git`.
+///
+/// The build timestamp is the time the build script last ran. Cargo only re-runs it
+/// when the git `HEAD`, the refs or the override variables change, so rebuilds of
+/// uncommitted changes keep the timestamp of the previous run.
+pub fn emit_build_info() {
+    let ref_name = env::var("GIT_REF_NAME")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--abbrev-ref", "HEAD"]));
+    let commit_hash = env::var("GIT_COMMIT_HASH")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]));
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+
+    let mut features = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+
+    emit(REF_NAME_VAR, ref_name);
+    emit(COMMIT_HASH_VAR, commit_hash);
+    emit(BUILD_TIMESTAMP_VAR, Some(timestamp()));
+    emit(RUSTC_VERSION_VAR, rustc_version);
+    emit(FEATURES_VAR, Some(features.join(",")));
+
+    println!("cargo:rerun-if-env-changed=GIT_REF_NAME");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT_HASH");
+    if let Some(git_dir) = command_output("git", &["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs", git_dir);
+    }
+}
+
+fn emit(name: &str, value: Option<String>) {
+    if let Some(value) = value {
+        println!("cargo:rustc-env={}={}", name, value);
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!output.is_empty()).then_some(output)
+}
+
+fn timestamp() -> String {
+    rfc3339(OffsetDateTime::now_utc())
+}
+
+/// Formats `time` as an RFC 3339 timestamp.
+fn rfc3339(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339)
+        .expect("timestamps in the RFC 3339 range are formatted")
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::rfc3339;
+
+    #[test]
+    fn timestamps_are_formatted() {
+        let at = |secs| rfc3339(OffsetDateTime::from_unix_timestamp(secs).unwrap());
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+}
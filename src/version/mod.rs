@@ -0,0 +1,128 @@
+//! Build metadata for [`Version`].
+//!
+//! A service fills it in two steps: its `build.rs` calls `emit_build_info`, which
+//! needs the `build-info` feature of the build dependency, and the service reads
+//! the result with [`build_version!`](crate::build_version):
+//!
+//! ```ignore
+//! // Cargo.toml
+//! [build-dependencies]
+//! zkbob-utils-rs = { ..., features = ["build-info"] }
+//!
+//! // build.rs
+//! fn main() {
+//!     zkbob_utils_rs::version::emit_build_info();
+//! }
+//!
+//! // main.rs
+//! let version = zkbob_utils_rs::build_version!();
+//! ```
+
+use actix_web::{web, HttpResponse};
+use opentelemetry::KeyValue;
+
+use crate::configuration::Version;
+
+#[cfg(feature = "build-info")]
+mod build;
+
+#[cfg(feature = "build-info")]
+pub use build::emit_build_info;
+
+pub const REF_NAME_VAR: &str = crate::__build_var!(ref_name);
+pub const COMMIT_HASH_VAR: &str = crate::__build_var!(commit_hash);
+pub const BUILD_TIMESTAMP_VAR: &str = crate::__build_var!(build_timestamp);
+pub const RUSTC_VERSION_VAR: &str = crate::__build_var!(rustc_version);
+pub const FEATURES_VAR: &str = crate::__build_var!(features);
+
+/// Names of the variables set by `emit_build_info`, as literals for `option_env!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __build_var {
+    (ref_name) => {
+        "ZKBOB_BUILD_REF_NAME"
+    };
+    (commit_hash) => {
+        "ZKBOB_BUILD_COMMIT_HASH"
+    };
+    (build_timestamp) => {
+        "ZKBOB_BUILD_TIMESTAMP"
+    };
+    (rustc_version) => {
+        "ZKBOB_BUILD_RUSTC_VERSION"
+    };
+    (features) => {
+        "ZKBOB_BUILD_FEATURES"
+    };
+}
+
+/// Builds [`Version`] from the variables set by `emit_build_info` for the calling crate.
+#[macro_export]
+macro_rules! build_version {
+    () => {
+        $crate::configuration::Version {
+            package_version: option_env!("CARGO_PKG_VERSION").map(str::to_string),
+            ref_name: option_env!($crate::__build_var!(ref_name)).map(str::to_string),
+            commit_hash: option_env!($crate::__build_var!(commit_hash)).map(str::to_string),
+            build_timestamp: option_env!($crate::__build_var!(build_timestamp)).map(str::to_string),
+            rustc_version: option_env!($crate::__build_var!(rustc_version)).map(str::to_string),
+            features: option_env!($crate::__build_var!(features))
+                .map(|features| {
+                    features
+                        .split(',')
+                        .filter(|feature| !feature.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    };
+}
+
+impl Version {
+    /// OpenTelemetry resource attributes describing the build.
+    pub fn resource_attributes(&self) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        let mut push = |key: &'static str, value: &Option<String>| {
+            if let Some(value) = value {
+                attributes.push(KeyValue::new(key, value.clone()));
+            }
+        };
+        push("service.version", &self.package_version);
+        push("vcs.ref_name", &self.ref_name);
+        push("vcs.commit_hash", &self.commit_hash);
+        push("build.timestamp", &self.build_timestamp);
+        push("build.rustc_version", &self.rustc_version);
+        if !self.features.is_empty() {
+            attributes.push(KeyValue::new("build.features", self.features.join(",")));
+        }
+        attributes
+    }
+}
+
+/// Handler answering with the [`Version`] registered as app data, see [`configure`].
+pub async fn handler(version: web::Data<Version>) -> HttpResponse {
+    HttpResponse::Ok().json(version.get_ref())
+}
+
+/// Registers `GET /version`, e.g. `App::new().configure(version::configure(version))`.
+pub fn configure(version: Version) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |config| {
+        config
+            .app_data(web::Data::new(version))
+            .route("/version", web::get().to(handler));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn version_is_built() {
+        let version = crate::build_version!();
+        assert_eq!(
+            version.package_version.as_deref(),
+            option_env!("CARGO_PKG_VERSION")
+        );
+        assert!(version.build_timestamp.is_none());
+    }
+}
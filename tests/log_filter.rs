@@ -0,0 +1,88 @@
+//! Installs the global subscriber, so it runs in its own test binary.
+
+use std::{fs, time::Duration};
+
+use tokio::sync::watch;
+use zkbob_utils_rs::{
+    configuration::{Environment, LogLevel, OutputKind, TelemetryOutput, TelemetrySettings},
+    telemetry::telemetry::{follow_log_filter, set_log_filter, set_log_level, setup},
+};
+
+#[tokio::test]
+async fn log_filter_follows_settings() {
+    std::env::remove_var("RUST_LOG");
+    let dir = std::env::temp_dir().join(format!("zkbob-log-filter-{}", std::process::id()));
+    let path = dir.join("service.log");
+    let settings = TelemetrySettings {
+        kind: None,
+        endpoint: None,
+        log_level: LogLevel::INFO,
+        log_directives: Vec::new(),
+        service_name: "test".to_string(),
+        log_format: None,
+        otlp: Default::default(),
+        outputs: vec![TelemetryOutput {
+            kind: OutputKind::File {
+                path: path.clone(),
+                rotation: Default::default(),
+                max_size_mb: None,
+                max_files: None,
+            },
+            log_level: None,
+        }],
+        sampling: Default::default(),
+        resource_attributes: Default::default(),
+        redaction: Default::default(),
+        propagation: None,
+        environment: Environment::Test,
+    };
+    let guard = setup(&settings).unwrap();
+
+    tracing::debug!("hidden debug");
+    tracing::info!("shown info");
+
+    set_log_filter(LogLevel::DEBUG, &["noisy=warn".to_string()]).unwrap();
+    tracing::debug!("shown debug");
+    tracing::info!(target: "noisy", "hidden noisy info");
+
+    // keeps the directives
+    set_log_level(LogLevel::ERROR).unwrap();
+    tracing::warn!("hidden warn");
+    tracing::warn!(target: "noisy", "shown noisy warn");
+
+    let (sender, receiver) = watch::channel(settings.clone());
+    follow_log_filter(receiver, |settings| settings);
+    sender
+        .send(TelemetrySettings {
+            log_directives: vec!["noisy=off".to_string()],
+            ..settings
+        })
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    tracing::info!("shown again info");
+    tracing::error!(target: "noisy", "hidden noisy error");
+
+    guard.shutdown();
+    let logs = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let shown = logs
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|line| line["msg"].as_str().map(str::to_string))
+        .filter(|msg| {
+            msg.contains(" info")
+                || msg.contains(" debug")
+                || msg.contains(" warn")
+                || msg.contains(" error")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        shown,
+        [
+            "shown info",
+            "shown debug",
+            "shown noisy warn",
+            "shown again info"
+        ]
+    );
+}
@@ -0,0 +1,17 @@
+//! Tests installing the global subscriber, kept out of the unit tests that
+//! install their own default subscribers.
+
+use zkbob_utils_rs::telemetry::{
+    error::TelemetryError,
+    telemetry::{init_pretty, init_sink},
+};
+
+#[test]
+fn second_init_fails() {
+    let guard = init_sink("test".to_string(), "info".to_string()).unwrap();
+    assert!(matches!(
+        init_pretty("debug".to_string()),
+        Err(TelemetryError::AlreadyInitialized)
+    ));
+    guard.shutdown();
+}